@@ -1,10 +1,28 @@
-use std::{collections::HashMap, io::Read, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use anyhow::{anyhow, Result};
-use clap::ArgAction;
+use clap::{parser::ValueSource, ArgAction};
 use itertools::Itertools;
 
-use crate::config::Config;
+use crate::{
+    cache::CacheSettings,
+    cargo, changes,
+    config::{resolve_path, split_program, Config, Loader},
+    format::Format,
+    init,
+    logs::LogSettings,
+    map,
+    multiplexer::{self, TaskDefinition},
+    parallelism,
+    plan::PlanFormat,
+    report::Report,
+    rerun,
+    selection::Selection,
+};
 
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) enum Privilege {
@@ -20,13 +38,42 @@ pub(crate) struct CallArgs {
 
 impl CallArgs {
     pub(crate) fn validate(&self) -> Result<()> {
+        // the outputs written to stdout would interleave
+        if let Command::Multiplex {
+            stdout,
+            events,
+            reports,
+            ..
+        } = &self.command
+        {
+            let writers = stdout
+                .iter()
+                .map(|_| "--stdout")
+                .chain(events.iter().filter(|v| *v == "-").map(|_| "--events -"))
+                .chain(reports.iter().filter(|v| v.path == "-").map(|_| "--report"))
+                .collect_vec();
+            if writers.len() > 1 {
+                return Err(anyhow!(
+                    "{} can not be combined, all of them write to stdout",
+                    writers.join(" and ")
+                ));
+            }
+        }
+
         if self.privileges == Privilege::Experimental {
             return Ok(());
         }
 
         match &self.command {
             | Command::Multiplex {
-                stdout, parallelism, ..
+                stdout,
+                parallelism,
+                events,
+                reports,
+                output,
+                logs,
+                max_load,
+                ..
             } => {
                 match stdout {
                     | Some(..) => Err(anyhow!("experimental flag (stdout)")),
@@ -37,6 +84,30 @@ impl CallArgs {
                     | Some(..) => Err(anyhow!("experimental flag (parallelism)")),
                     | None => Ok(()),
                 }?;
+
+                match events {
+                    | Some(..) => Err(anyhow!("experimental flag (events)")),
+                    | None => Ok(()),
+                }?;
+
+                if !reports.is_empty() {
+                    return Err(anyhow!("experimental flag (report)"));
+                }
+
+                match output {
+                    | Some(..) => Err(anyhow!("experimental flag (output)")),
+                    | None => Ok(()),
+                }?;
+
+                match logs {
+                    | Some(..) => Err(anyhow!("experimental flag (log-dir)")),
+                    | None => Ok(()),
+                }?;
+
+                match max_load {
+                    | Some(..) => Err(anyhow!("experimental flag (max-load)")),
+                    | None => Ok(()),
+                }?;
             },
             | _ => {},
         };
@@ -55,21 +126,54 @@ pub(crate) enum ManualFormat {
 pub enum StdoutFormat {
     #[cfg(feature = "format+json")]
     Json,
+    #[cfg(feature = "format+json")]
+    Jsonl,
     #[cfg(feature = "format+yaml")]
     Yaml,
+    #[cfg(feature = "format+toml")]
+    Toml,
+    #[cfg(feature = "format+ron")]
+    Ron,
+}
+
+impl FromStr for StdoutFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            #[cfg(feature = "format+json")]
+            | "json" => Ok(StdoutFormat::Json),
+            #[cfg(feature = "format+json")]
+            | "jsonl" => Ok(StdoutFormat::Jsonl),
+            #[cfg(feature = "format+yaml")]
+            | "yaml" => Ok(StdoutFormat::Yaml),
+            #[cfg(feature = "format+toml")]
+            | "toml" => Ok(StdoutFormat::Toml),
+            #[cfg(feature = "format+ron")]
+            | "ron" => Ok(StdoutFormat::Ron),
+            | _ => Err(anyhow!("unknown stdout format")),
+        }
+    }
 }
 
 impl StdoutFormat {
-    pub fn args() -> Vec<&'static str> {
-        let mut args = Vec::<_>::new();
+    pub const ARGS: &'static [&'static str] = &[
+        #[cfg(feature = "format+json")]
+        "json",
         #[cfg(feature = "format+json")]
-        args.push("json");
+        "jsonl",
         #[cfg(feature = "format+yaml")]
-        args.push("yaml");
-        args
-    }
+        "yaml",
+        #[cfg(feature = "format+toml")]
+        "toml",
+        #[cfg(feature = "format+ron")]
+        "ron",
+    ];
 }
 
+// there is a single instance per process, so the size of Multiplex does not
+// matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub(crate) enum Command {
     Manual {
@@ -80,19 +184,130 @@ pub(crate) enum Command {
         path: String,
         shell: clap_complete::Shell,
     },
+    Schema {
+        path: Option<String>,
+    },
+    List {
+        tasks: Vec<TaskDefinition>,
+    },
+    Validate {
+        files: Vec<PathBuf>,
+    },
+    Init {
+        path: PathBuf,
+        format: Format,
+        force: bool,
+    },
+    History {
+        limit: usize,
+    },
+    Show {
+        run: Option<String>,
+        logs: bool,
+    },
 
     Multiplex {
-        program: Vec<String>,
         stdout: Option<StdoutFormat>,
         stderr: usize,
-        commands: Vec<String>,
+        commands: Vec<TaskDefinition>,
         parallelism: Option<usize>,
+        events: Option<String>,
+        reports: Vec<Report>,
+        output: Option<OutputFile>,
+        logs: Option<LogSettings>,
+        cache: CacheSettings,
+        max_load: Option<f64>,
+        dry_run: Option<PlanFormat>,
+        history: bool,
     },
 }
 
+#[derive(Debug)]
+pub(crate) struct OutputFile {
+    pub path: PathBuf,
+    pub format: Format,
+}
+
 pub(crate) struct ClapArgumentLoader {}
 
 impl ClapArgumentLoader {
+    // the arguments of a run, shared by the root command and the subcommands that
+    // run tasks
+    fn runtime_args() -> Vec<clap::Arg> {
+        vec![
+            clap::Arg::new("program")
+                .long("program")
+                .help("Defines the program used to execute the commands given. Overrides the defaults of config files.")
+                .default_value("/bin/sh -c"),
+            clap::Arg::new("stderr")
+                .long("stderr")
+                .help("Defines the length of stderr to display.")
+                .default_value("3"),
+            clap::Arg::new("stdout")
+                .long("stdout")
+                .help(
+                    "Marks whether the stdout of the processes are captured and returned in a structured format to \
+                     stdout. The \"jsonl\" format streams one result per line as soon as each task completes.",
+                )
+                .value_parser(StdoutFormat::ARGS.to_vec()),
+            clap::Arg::new("output").short('o').long("output").help(
+                "Writes the structured result to the given file. The format is inferred from the file extension. The \
+                 (partial) result is also written if the run is interrupted.",
+            ),
+            clap::Arg::new("log-dir").long("log-dir").help(
+                "Tees the stdout and stderr of every task into log files in the given directory. The \"log\" setting \
+                 of a command selects between \"split\" (default), \"combined\" and \"off\".",
+            ),
+            clap::Arg::new("log-max-size")
+                .long("log-max-size")
+                .help("Rotates a log file once it exceeds the given size in bytes.")
+                .requires("log-dir"),
+            clap::Arg::new("log-max-files")
+                .long("log-max-files")
+                .help("The number of rotated generations kept of every log file.")
+                .default_value("5"),
+            clap::Arg::new("events").long("events").help(
+                "Writes every task event (status changes, stdout and stderr lines) as newline-delimited JSON to the \
+                 given file or to stdout when set to \"-\".",
+            ),
+            clap::Arg::new("report")
+                .long("report")
+                .help(
+                    "Writes a report of the run in the given format to a file (<format>:<path>) or to stdout. Can be \
+                     given multiple times.",
+                )
+                .long_help(format!(
+                    "Writes a report of the run in the given format to a file (<format>:<path>) or to stdout. Can be \
+                     given multiple times. Supported formats: {}.",
+                    Report::args().join(", ")
+                ))
+                .action(ArgAction::Append),
+            clap::Arg::new("dry-run")
+                .long("dry-run")
+                .help(
+                    "Prints the fully resolved plan (argv, cwd, environment, dependencies and scheduling order of \
+                     every task) instead of running it.",
+                )
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("table")
+                .value_parser(PlanFormat::args()),
+            clap::Arg::new("parallelism").long("parallelism").short('p').help(
+                "Set the maximum amount of (sub) processes that run in parallel. Either a number, \"auto\" (one per \
+                 available CPU), a percentage of the CPUs like \"50%\" or an offset like \"cores-1\". Defaults to the \
+                 number of tasks.",
+            ),
+            clap::Arg::new("max-load").long("max-load").help(
+                "Holds off starting tasks while the 1-minute load average of the system is above the given value, \
+                 unless no task is running (Linux only).",
+            ),
+            clap::Arg::new("no-history")
+                .long("no-history")
+                .help("Does not store the run, including the output of its tasks, in the history.")
+                .num_args(0),
+        ]
+    }
+
     pub(crate) fn root_command() -> clap::Command {
         clap::Command::new("bobr")
             .version(env!("CARGO_PKG_VERSION"))
@@ -100,31 +315,47 @@ impl ClapArgumentLoader {
             .author("Alexander Weber (cchexcode) <alexanderh.weber@outlook.com>")
             .propagate_version(true)
             .subcommand_required(false)
-            .args([
+            .arg(
                 clap::Arg::new("experimental")
+                    .global(true)
                     .short('e')
                     .long("experimental")
                     .help("Enables experimental features.")
                     .num_args(0),
-                clap::Arg::new("program")
-                    .long("program")
-                    .help("Defines the program used to execute the commands given.")
-                    .default_value("/bin/sh -c"),
-                clap::Arg::new("stderr")
-                    .long("stderr")
-                    .help("Defines the length of stderr to display.")
-                    .default_value("3"),
-                clap::Arg::new("stdout")
-                    .long("stdout")
+            )
+            .args(Self::runtime_args())
+            .args([
+                clap::Arg::new("set")
+                    .long("set")
                     .help(
-                        "Marks whether the stdout of the processes are captured and returned in a structured format \
-                         to stdout.",
+                        "Sets a variable (key=value) for the templates in config files, overriding their [vars] table.",
                     )
-                    .value_parser(StdoutFormat::args()),
-                clap::Arg::new("parallelism")
-                    .long("parallelism")
-                    .short('p')
-                    .help("Set the maximum amount of (sub) processes that run in parallel."),
+                    .action(ArgAction::Append),
+                clap::Arg::new("only")
+                    .long("only")
+                    .help(
+                        "Only runs the tasks matching the given name, tag or glob pattern (and the tasks they depend \
+                         on). Can be given multiple times.",
+                    )
+                    .action(ArgAction::Append),
+                clap::Arg::new("skip")
+                    .long("skip")
+                    .help(
+                        "Does not run the tasks matching the given name, tag or glob pattern. Can be given multiple \
+                         times.",
+                    )
+                    .action(ArgAction::Append),
+                clap::Arg::new("changed-since")
+                    .long("changed-since")
+                    .value_name("GIT_REF")
+                    .help(
+                        "Skips the tasks declaring paths if no file matching them changed since the merge base with \
+                         the given git ref (like \"origin/main\").",
+                    ),
+                clap::Arg::new("no-cache")
+                    .long("no-cache")
+                    .help("Runs the tasks declaring inputs even if their result is cached, and caches the new result.")
+                    .action(ArgAction::SetTrue),
                 clap::Arg::new("command")
                     .short('c')
                     .long("command")
@@ -134,11 +365,167 @@ impl ClapArgumentLoader {
                     .short('f')
                     .long("file")
                     .help(
-                        "Define a commands file. The content will be split per line, which are then interpreted as \
-                         individual commands.",
+                        "Define a config file (or \"-\" to read it from stdin). The format is inferred from the file \
+                         extension or else from the content. If neither commands nor files are given, bobr.toml, \
+                         bobr.yaml or bobr.json is looked up in the current directory and its parents.",
+                    )
+                    .action(ArgAction::Append),
+                clap::Arg::new("format")
+                    .long("format")
+                    .help("Sets the format of the config files given, for files without extension or stdin.")
+                    .value_parser(Format::names()),
+                clap::Arg::new("make-target")
+                    .long("make-target")
+                    .value_name("TARGET")
+                    .help(
+                        "Imports the given target of the Makefiles given as tasks, instead of all targets declared as \
+                         .PHONY.",
                     )
                     .action(ArgAction::Append),
             ])
+            .subcommand(
+                clap::Command::new("map")
+                    .about("Runs a command template once per input read from stdin, like GNU parallel.")
+                    .args(Self::runtime_args())
+                    .arg(
+                        clap::Arg::new("command")
+                            .short('c')
+                            .long("command")
+                            .help(
+                                "The command template. {} is replaced with the (shell quoted) input, {.} with the \
+                                 input without extension, {/} with its basename and {#} with the sequence number of \
+                                 the task. Without placeholders, the inputs are appended.",
+                            )
+                            .required(true),
+                    )
+                    .arg(
+                        clap::Arg::new("input-from")
+                            .long("input-from")
+                            .help("Reads the inputs from the given file instead of stdin."),
+                    )
+                    .arg(
+                        clap::Arg::new("null")
+                            .short('0')
+                            .long("null")
+                            .help("Inputs are separated by NUL characters instead of newlines.")
+                            .num_args(0),
+                    )
+                    .arg(
+                        clap::Arg::new("max-args")
+                            .short('n')
+                            .long("max-args")
+                            .help("Passes up to the given amount of inputs to each command.")
+                            .default_value("1"),
+                    ),
+            )
+            .subcommand(
+                clap::Command::new("cargo")
+                    .about(
+                        "Runs a cargo subcommand once per workspace member (cargo <subcommand> -p <member> [args]), \
+                         ordered by the dependencies between the members.",
+                    )
+                    .args(Self::runtime_args())
+                    .arg(
+                        clap::Arg::new("subcommand")
+                            .required(true)
+                            .help("The cargo subcommand, like \"test\"."),
+                    )
+                    .arg(
+                        clap::Arg::new("args")
+                            .help("Arguments passed on to every cargo invocation.")
+                            .num_args(0..)
+                            .allow_hyphen_values(true),
+                    )
+                    .arg(
+                        clap::Arg::new("escaped")
+                            .help("Arguments after \"--\", passed on to every cargo invocation after a \"--\".")
+                            .num_args(0..)
+                            .last(true),
+                    ),
+            )
+            .subcommand(
+                clap::Command::new("rerun")
+                    .about(
+                        "Runs the tasks of a previous run again, with the program, cwd and environment they were \
+                         resolved to.",
+                    )
+                    .args(Self::runtime_args())
+                    .arg(clap::Arg::new("run").help(
+                        "The id of a run in the history or the path to a JSON result (like written by --output). \
+                         Defaults to the latest run.",
+                    ))
+                    .arg(
+                        clap::Arg::new("failed")
+                            .long("failed")
+                            .help("Only runs the tasks that failed, were cancelled or did not complete.")
+                            .num_args(0),
+                    ),
+            )
+            .subcommand(
+                clap::Command::new("schema")
+                    .about("Renders the JSON Schema of config files.")
+                    .arg(
+                        clap::Arg::new("out")
+                            .short('o')
+                            .long("out")
+                            .help("The file to write the schema to. Defaults to stdout."),
+                    ),
+            )
+            .subcommand(
+                clap::Command::new("list")
+                    .about("Lists the tasks of config files with their tags and descriptions.")
+                    .arg(
+                        clap::Arg::new("file")
+                            .short('f')
+                            .long("file")
+                            .help("The config file to list. Defaults to the discovered bobr.toml (or .yaml, .json).")
+                            .action(ArgAction::Append),
+                    ),
+            )
+            .subcommand(
+                clap::Command::new("validate")
+                    .about(
+                        "Checks config files (including the files they include) without running them. Exits with an \
+                         error if any of them is invalid.",
+                    )
+                    .arg(clap::Arg::new("files").required(true).num_args(1..)),
+            )
+            .subcommand(
+                clap::Command::new("init")
+                    .about("Writes a commented starter config file.")
+                    .arg(clap::Arg::new("path").help(
+                        "The file to write, its format is inferred from the extension. Defaults to bobr.toml (or \
+                         bobr.yaml, bobr.json if TOML is not supported).",
+                    ))
+                    .arg(
+                        clap::Arg::new("force")
+                            .long("force")
+                            .help("Overwrites an existing file.")
+                            .num_args(0),
+                    ),
+            )
+            .subcommand(
+                clap::Command::new("history")
+                    .about("Lists the latest runs, which are stored in $XDG_STATE_HOME/bobr.")
+                    .arg(
+                        clap::Arg::new("limit")
+                            .short('n')
+                            .long("limit")
+                            .help("The number of runs to list.")
+                            .default_value("20"),
+                    ),
+            )
+            .subcommand(
+                clap::Command::new("show")
+                    .about("Shows a run of the history with the outcome of its tasks and the stderr of failed tasks.")
+                    .arg(clap::Arg::new("run").help("The id of the run. Defaults to the latest run."))
+                    .arg(
+                        clap::Arg::new("logs")
+                            .long("logs")
+                            .help("Shows the stdout and stderr of all tasks.")
+                            .num_args(0),
+                    ),
+            )
             .subcommand(
                 clap::Command::new("man")
                     .about("Renders the manual.")
@@ -188,69 +575,286 @@ impl ClapArgumentLoader {
                 path: subc.get_one::<String>("out").unwrap().into(),
                 shell: clap_complete::Shell::from_str(subc.get_one::<String>("shell").unwrap().as_str()).unwrap(),
             }
-        } else {
-            let mut commands = command
-                .get_many::<String>("command")
+        } else if let Some(subc) = command.subcommand_matches("schema") {
+            Command::Schema {
+                path: subc.get_one::<String>("out").cloned(),
+            }
+        } else if let Some(subc) = command.subcommand_matches("list") {
+            let files = subc
+                .get_many::<String>("file")
                 .unwrap_or_default()
-                .cloned()
+                .map(PathBuf::from)
                 .collect_vec();
-            if let Some(files) = command.get_many::<String>("file") {
-                for file in files {
-                    let mut content = String::new();
-                    std::fs::File::open(file)?.read_to_string(&mut content)?;
-
-                    let mut filters = HashMap::<&str, Box<dyn Fn(&str) -> Result<Config>>>::new();
-                    #[cfg(feature = "format+yaml")]
-                    {
-                        // meh...
-                        filters.insert(".yml", Box::new(move |v| Ok(serde_yml::from_str::<Config>(&v)?)));
-                        filters.insert(".yaml", Box::new(move |v| Ok(serde_yml::from_str::<Config>(&v)?)));
+            let mut loader = Loader::new(
+                split_program(command.get_one::<String>("program").unwrap())?,
+                false,
+                BTreeMap::new(),
+            );
+            if files.is_empty() {
+                let path =
+                    Config::discover(&std::env::current_dir()?)?.ok_or_else(|| anyhow!("no config file found"))?;
+                loader.load_discovered(&path)?;
+            }
+            for file in files {
+                loader.load(&file)?;
+            }
+            Command::List {
+                tasks: loader.finish()?,
+            }
+        } else if let Some(subc) = command.subcommand_matches("validate") {
+            Command::Validate {
+                files: subc.get_many::<String>("files").unwrap().map(PathBuf::from).collect(),
+            }
+        } else if let Some(subc) = command.subcommand_matches("history") {
+            Command::History {
+                limit: subc.get_one::<String>("limit").unwrap().parse::<usize>()?,
+            }
+        } else if let Some(subc) = command.subcommand_matches("show") {
+            Command::Show {
+                run: subc.get_one::<String>("run").cloned(),
+                logs: subc.get_flag("logs"),
+            }
+        } else if let Some(subc) = command.subcommand_matches("init") {
+            let path = match subc.get_one::<String>("path") {
+                | Some(v) => PathBuf::from(v),
+                | None => PathBuf::from(format!("bobr.{}", init::default_format()?.extensions()[0])),
+            };
+            Command::Init {
+                format: Format::from_path(&path)?,
+                path,
+                force: subc.get_flag("force"),
+            }
+        } else {
+            // command line values take precedence over the defaults of config files
+            // the arguments of the run are given to the subcommand if there is one
+            let run = match command.subcommand() {
+                | Some(("map" | "cargo" | "rerun", subc)) => subc,
+                | _ => &command,
+            };
+            let explicit = |id: &str| run.value_source(id) == Some(ValueSource::CommandLine);
+            let cli_program = split_program(run.get_one::<String>("program").unwrap())?;
+            let mut stderr = run.get_one::<String>("stderr").unwrap().parse::<usize>()?;
+            let mut stdout = run.get_one::<String>("stdout").cloned();
+            let mut history = !run.get_flag("no-history");
+            let mut parallelism = match run.get_one::<String>("parallelism") {
+                | Some(v) => Some(parallelism::parse(v)?),
+                | None => None,
+            };
+            let max_load = match run.get_one::<String>("max-load") {
+                | Some(v) => {
+                    if multiplexer::load_average().is_none() {
+                        return Err(anyhow!("--max-load needs /proc/loadavg, which is not available"));
+                    }
+                    Some(v.parse::<f64>().map_err(|_| anyhow!("invalid max-load \"{}\"", v))?)
+                },
+                | None => None,
+            };
+
+            // the cache is kept next to the (first) config file
+            let mut cache_dir = PathBuf::from(".bobr/cache");
+            let commands = if let Some((name @ ("map" | "cargo" | "rerun"), subc)) = command.subcommand() {
+                if ["command", "file", "set", "format"]
+                    .iter()
+                    .any(|v| command.contains_id(v))
+                {
+                    return Err(anyhow!("{} can not be combined with commands or files", name));
+                }
+                let misplaced = Self::runtime_args()
+                    .into_iter()
+                    .map(|v| v.get_id().to_string())
+                    .find(|v| command.value_source(v) == Some(ValueSource::CommandLine));
+                if let Some(arg) = misplaced {
+                    return Err(anyhow!("--{} has to be given after {}", arg, name));
+                }
+                if name == "rerun" {
+                    rerun::tasks(
+                        subc.get_one::<String>("run").map(|v| v.as_str()),
+                        subc.get_flag("failed"),
+                    )?
+                } else if name == "cargo" {
+                    let mut args = subc
+                        .get_many::<String>("args")
+                        .unwrap_or_default()
+                        .cloned()
+                        .collect_vec();
+                    // clap consumes a "--" directly after the subcommand, it is kept for cargo
+                    if let Some(escaped) = subc.get_many::<String>("escaped") {
+                        args.push("--".to_owned());
+                        args.extend(escaped.cloned());
+                    }
+                    cargo::tasks(subc.get_one::<String>("subcommand").unwrap(), &args, &cli_program)?
+                } else {
+                    let content = match subc.get_one::<String>("input-from").map(|v| v.as_str()) {
+                        | Some("-") | None => std::io::read_to_string(std::io::stdin())?,
+                        | Some(path) => std::fs::read_to_string(path)?,
+                    };
+                    let separator = if subc.get_flag("null") { '\0' } else { '\n' };
+                    let batch = subc.get_one::<String>("max-args").unwrap().parse::<usize>()?;
+                    if batch == 0 {
+                        return Err(anyhow!("max-args must be greater than 0"));
+                    }
+                    map::tasks(
+                        subc.get_one::<String>("command").unwrap(),
+                        &map::records(&content, separator),
+                        batch,
+                        &cli_program,
+                    )
+                }
+            } else {
+                let mut commands = command
+                    .get_many::<String>("command")
+                    .unwrap_or_default()
+                    .map(|v| {
+                        TaskDefinition {
+                            name: None,
+                            description: None,
+                            program: cli_program.clone(),
+                            command: v.clone(),
+                            cwd: None,
+                            env: BTreeMap::new(),
+                            log: None,
+                            tags: Vec::new(),
+                            depends_on: Vec::new(),
+                            order_only: false,
+                            source: None,
+                            paths: Vec::new(),
+                            skip: None,
+                            cache: None,
+                        }
+                    })
+                    .collect_vec();
+                let mut files = command
+                    .get_many::<String>("file")
+                    .unwrap_or_default()
+                    .map(PathBuf::from)
+                    .collect_vec();
+                let mut discovered = false;
+                if files.is_empty() && commands.is_empty() {
+                    if let Some(path) = Config::discover(&std::env::current_dir()?)? {
+                        eprintln!("Loading config file: {}", path.display());
+                        files.push(path);
+                        discovered = true;
                     }
-                    #[cfg(feature = "format+json")]
-                    filters.insert(".json", Box::new(move |v| Ok(serde_json::from_str::<Config>(&v)?)));
-                    #[cfg(feature = "format+toml")]
-                    filters.insert(".toml", Box::new(move |v| Ok(toml::from_str::<Config>(&v)?)));
-
-                    let mut config = Option::<Config>::None;
-                    for (format, parser) in filters.iter() {
-                        if file.ends_with(format) {
-                            config = Some(parser(&content)?);
-                            break;
+                }
+                let vars = command
+                    .get_many::<String>("set")
+                    .unwrap_or_default()
+                    .map(|v| {
+                        v.split_once('=')
+                            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                            .ok_or_else(|| anyhow!("invalid variable \"{}\" (expected key=value)", v))
+                    })
+                    .collect::<Result<BTreeMap<_, _>>>()?;
+                let format = command
+                    .get_one::<String>("format")
+                    .map(|v| Format::from_name(v))
+                    .transpose()?;
+                if let Some(file) = files.first() {
+                    cache_dir = resolve_path(file, ".bobr/cache");
+                }
+                let make_targets = command
+                    .get_many::<String>("make-target")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect_vec();
+                let mut loader = Loader::new(cli_program, explicit("program"), vars)
+                    .with_format(format)
+                    .with_make_targets(make_targets);
+                for file in files {
+                    let defaults = match discovered {
+                        | true => loader.load_discovered(&file)?,
+                        | false => loader.load(&file)?,
+                    };
+                    // experimental settings of config files are named along with the file
+                    let experimental = |key: &str| {
+                        match privileges {
+                            | Privilege::Experimental => Ok(()),
+                            | Privilege::Normal => {
+                                Err(anyhow!(
+                                    "experimental setting ({} in \"{}\"), pass -e to enable it",
+                                    key,
+                                    file.display()
+                                ))
+                            },
                         }
+                    };
+                    // run-wide settings of later files override earlier ones
+                    if let Some(v) = defaults.stderr.filter(|_| !explicit("stderr")) {
+                        stderr = v;
+                    }
+                    if let Some(v) = defaults.stdout.filter(|_| !explicit("stdout")) {
+                        experimental("defaults.stdout")?;
+                        stdout = Some(v);
+                    }
+                    if let Some(v) = defaults.parallelism.filter(|_| !explicit("parallelism")) {
+                        experimental("defaults.parallelism")?;
+                        parallelism = Some(v.resolve()?);
+                    }
+                    if let Some(v) = defaults.history.filter(|_| !explicit("no-history")) {
+                        history = v;
                     }
-                    // add error handling
-                    let config = config.unwrap();
-
-                    let mut cmds = config.commands.into_iter().map(|v| v.command).collect::<Vec<_>>();
-                    commands.append(&mut cmds);
                 }
+                commands.append(&mut loader.finish()?);
+                commands
+            };
+            let selection = Selection {
+                only: command
+                    .get_many::<String>("only")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                skip: command
+                    .get_many::<String>("skip")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+            };
+            let mut commands = selection.apply(commands)?;
+            if let Some(git_ref) = command.get_one::<String>("changed-since") {
+                changes::mark(&mut commands, git_ref)?;
             }
 
-            let program = command
-                .get_one::<String>("program")
-                .unwrap()
-                .split_whitespace()
-                .into_iter()
-                .map(|v| v.to_owned())
-                .collect::<Vec<_>>();
             Command::Multiplex {
-                program,
-                stderr: command.get_one::<String>("stderr").unwrap().parse::<usize>()?,
-                stdout: match command.get_one::<String>("stdout") {
+                stderr,
+                cache: CacheSettings {
+                    dir: cache_dir,
+                    refresh: command.get_flag("no-cache"),
+                },
+                max_load,
+                history,
+                stdout: stdout.map(|v| StdoutFormat::from_str(&v)).transpose()?,
+                commands,
+                parallelism,
+                logs: match run.get_one::<String>("log-dir") {
                     | Some(v) => {
-                        match v.as_ref() {
-                            #[cfg(feature = "format+json")]
-                            | "json" => Ok(Some(StdoutFormat::Json)),
-                            #[cfg(feature = "format+yaml")]
-                            | "yaml" => Ok(Some(StdoutFormat::Yaml)),
-                            | _ => Err(anyhow!("unknown stdout format")),
-                        }
+                        Some(LogSettings {
+                            dir: PathBuf::from(v),
+                            max_size: match run.get_one::<String>("log-max-size") {
+                                | Some(v) => Some(v.parse::<u64>()?),
+                                | None => None,
+                            },
+                            max_files: run.get_one::<String>("log-max-files").unwrap().parse::<usize>()?,
+                        })
+                    },
+                    | None => None,
+                },
+                events: run.get_one::<String>("events").cloned(),
+                reports: run
+                    .get_many::<String>("report")
+                    .unwrap_or_default()
+                    .map(|v| Report::parse(v))
+                    .collect::<Result<Vec<_>>>()?,
+                dry_run: run
+                    .get_one::<String>("dry-run")
+                    .map(|v| PlanFormat::parse(v))
+                    .transpose()?,
+                output: match run.get_one::<String>("output") {
+                    | Some(v) => {
+                        Some(OutputFile {
+                            path: PathBuf::from(v),
+                            format: Format::from_path(Path::new(v))?,
+                        })
                     },
-                    | None => Ok(None),
-                }?,
-                commands,
-                parallelism: match command.get_one::<String>("parallelism") {
-                    | Some(v) => Some(v.parse::<usize>().unwrap()),
                     | None => None,
                 },
             }
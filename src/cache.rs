@@ -0,0 +1,174 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+
+// the least recently used records are removed when a new one is stored
+const RETAINED_RECORDS: usize = 256;
+
+// what the result of a task depends on, besides its argv and cwd
+#[derive(Debug, Clone, Default)]
+pub struct CacheSpec {
+    // glob patterns of the files read by the task
+    pub inputs: Vec<PathBuf>,
+    // glob patterns of the files written by the task, restored on a cache hit
+    pub outputs: Vec<PathBuf>,
+    // names of the environment variables whose values matter
+    pub env: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheSettings {
+    pub dir: PathBuf,
+    // runs the tasks even if a record exists, their results are still stored
+    pub refresh: bool,
+}
+
+// the outcome of a successful run, the outputs are stored next to it
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CacheRecord {
+    pub stdout: String,
+    pub stderr: String,
+    pub outputs: Vec<PathBuf>,
+}
+
+impl CacheSettings {
+    // hashes everything the result of a task depends on
+    pub fn key(
+        &self,
+        spec: &CacheSpec,
+        argv: &[String],
+        cwd: Option<&Path>,
+        env: &BTreeMap<String, String>,
+    ) -> Result<String> {
+        let mut hasher = Sha256::new();
+        // every value is terminated, so neighbouring values can not be confused
+        fn update(hasher: &mut Sha256, value: &[u8]) {
+            hasher.update(value);
+            hasher.update([0]);
+        }
+        for arg in argv {
+            update(&mut hasher, arg.as_bytes());
+        }
+        update(
+            &mut hasher,
+            cwd.map(|v| v.to_string_lossy().into_owned())
+                .unwrap_or_default()
+                .as_bytes(),
+        );
+        for name in &spec.env {
+            let value = env.get(name).cloned().or_else(|| std::env::var(name).ok());
+            update(
+                &mut hasher,
+                format!("{}={}", name, value.unwrap_or_default()).as_bytes(),
+            );
+        }
+        for pattern in &spec.outputs {
+            update(&mut hasher, pattern.to_string_lossy().as_bytes());
+        }
+        // the files are streamed, they can be large
+        for file in expand(&spec.inputs)? {
+            update(&mut hasher, file.to_string_lossy().as_bytes());
+            std::io::copy(&mut std::fs::File::open(&file)?, &mut hasher)?;
+            hasher.update([0]);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    // the record of a cached run, if there is one
+    pub fn lookup(&self, key: &str) -> Result<Option<CacheRecord>> {
+        match std::fs::read_to_string(self.dir.join(key).join("record.json")) {
+            | Ok(v) => Ok(Some(serde_json::from_str::<CacheRecord>(&v)?)),
+            | Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            | Err(e) => Err(e.into()),
+        }
+    }
+
+    // restores the outputs of a cached run
+    pub fn restore(&self, key: &str, record: &CacheRecord) -> Result<()> {
+        let dir = self.dir.join(key);
+        // a hit counts as use of the record
+        std::fs::File::options()
+            .append(true)
+            .open(dir.join("record.json"))?
+            .set_modified(SystemTime::now())?;
+        for (i, path) in record.outputs.iter().enumerate() {
+            if let Some(parent) = path.parent().filter(|v| !v.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(dir.join(i.to_string()), path)?;
+        }
+        Ok(())
+    }
+
+    // the record is written last, so an interrupted store is never a hit
+    pub fn store(&self, key: &str, spec: &CacheSpec, stdout: &str, stderr: &str) -> Result<()> {
+        let dir = self.dir.join(key);
+        std::fs::create_dir_all(&dir)?;
+        let outputs = expand(&spec.outputs)?;
+        for (i, path) in outputs.iter().enumerate() {
+            std::fs::copy(path, dir.join(i.to_string()))?;
+        }
+        let record = CacheRecord {
+            stdout: stdout.to_owned(),
+            stderr: stderr.to_owned(),
+            outputs,
+        };
+        let tmp = dir.join(format!(".record.json.{}.tmp", std::process::id()));
+        std::fs::write(&tmp, serde_json::to_string(&record)?)?;
+        std::fs::rename(&tmp, dir.join("record.json"))?;
+        self.evict()
+    }
+
+    fn evict(&self) -> Result<()> {
+        let mut records = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let dir = entry?.path();
+            // directories without a record are still being stored
+            if let Ok(modified) = dir.join("record.json").metadata().and_then(|v| v.modified()) {
+                records.push((modified, dir));
+            }
+        }
+        records.sort();
+        for (_, dir) in records.iter().rev().skip(RETAINED_RECORDS) {
+            // another run may have removed it already
+            match std::fs::remove_dir_all(dir) {
+                | Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                | _ => {},
+            }
+        }
+        Ok(())
+    }
+}
+
+// the sorted files matching the patterns, directories stand for all files in
+// them
+fn expand(patterns: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::<PathBuf>::new();
+    for pattern in patterns {
+        let paths = glob::glob(&pattern.to_string_lossy())
+            .map_err(|e| anyhow!("invalid pattern \"{}\": {}", pattern.display(), e))?;
+        for path in paths {
+            walk(&path?, &mut files)?;
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn walk(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            walk(&entry?.path(), files)?;
+        }
+    } else {
+        files.push(path.to_owned());
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+
+use crate::{map::quote, multiplexer::TaskDefinition};
+
+#[derive(serde::Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    workspace_members: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Package {
+    id: String,
+    name: String,
+    dependencies: Vec<Dependency>,
+}
+
+#[derive(serde::Deserialize)]
+struct Dependency {
+    name: String,
+    // none for normal dependencies
+    kind: Option<String>,
+}
+
+// one task per workspace member of the cargo project in the current directory,
+// depending on the tasks of the members it depends on
+pub fn tasks(subcommand: &str, args: &[String], program: &[String]) -> Result<Vec<TaskDefinition>> {
+    let output = std::process::Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .output()
+        .map_err(|e| anyhow!("failed to run cargo metadata: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let metadata = serde_json::from_slice::<Metadata>(&output.stdout)?;
+
+    let members = metadata
+        .packages
+        .iter()
+        .filter(|v| metadata.workspace_members.contains(&v.id))
+        .collect::<Vec<_>>();
+    let names = members.iter().map(|v| v.name.as_str()).collect::<Vec<_>>();
+    Ok(members
+        .iter()
+        .map(|member| {
+            // dev-dependencies are left out, they may depend on each other in cycles
+            let depends_on = member
+                .dependencies
+                .iter()
+                .filter(|v| v.kind.as_deref() != Some("dev") && names.contains(&v.name.as_str()))
+                .map(|v| v.name.clone())
+                .unique()
+                .collect();
+            let command = ["cargo", subcommand, "-p", &member.name]
+                .into_iter()
+                .chain(args.iter().map(|v| v.as_str()))
+                .map(quote)
+                .join(" ");
+            TaskDefinition {
+                name: Some(member.name.clone()),
+                description: None,
+                program: program.to_vec(),
+                command,
+                cwd: None,
+                env: BTreeMap::new(),
+                log: None,
+                tags: Vec::new(),
+                depends_on,
+                // the members are only ordered, a failing one does not cancel the others
+                order_only: true,
+                source: None,
+                paths: Vec::new(),
+                skip: None,
+                cache: None,
+            }
+        })
+        .collect())
+}
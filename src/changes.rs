@@ -0,0 +1,87 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::multiplexer::TaskDefinition;
+
+// marks the tasks declaring paths as skipped if none of the files changed since
+// the merge base with the given git ref (including uncommitted and untracked
+// files) matches them.
+// Tasks without paths always run.
+pub fn mark(tasks: &mut [TaskDefinition], git_ref: &str) -> Result<()> {
+    let changed = changed_files(git_ref)?;
+    for task in tasks.iter_mut().filter(|v| !v.paths.is_empty()) {
+        let patterns = task
+            .paths
+            .iter()
+            .map(|v| {
+                let pattern = normalize(&std::path::absolute(v)?);
+                glob::Pattern::new(&pattern.to_string_lossy())
+                    .map_err(|e| anyhow!("invalid path pattern \"{}\": {}", v.display(), e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // a pattern matching a directory matches all files in it
+        let touched = changed
+            .iter()
+            .any(|file| file.ancestors().any(|v| patterns.iter().any(|p| p.matches_path(v))));
+        if !touched {
+            task.skip = Some(format!("no changes since {}", git_ref));
+        }
+    }
+    Ok(())
+}
+
+fn changed_files(git_ref: &str) -> Result<Vec<PathBuf>> {
+    let root = git(&["rev-parse", "--show-toplevel"])?;
+    let root = PathBuf::from(root.trim_end());
+    // like a pull request, the changes are taken since the merge base of the ref
+    // and HEAD, so commits made to the ref in the meantime do not count. A rename
+    // is listed as the removed and the added file.
+    let changed = git(&["diff", "--name-only", "--no-renames", "--merge-base", git_ref, "--"])?;
+    // untracked files are no part of the diff, ls-files lists them relative to the
+    // current directory unless run from the root
+    let untracked = git(&[
+        "-C",
+        &root.to_string_lossy(),
+        "ls-files",
+        "--others",
+        "--exclude-standard",
+    ])?;
+    Ok(changed
+        .lines()
+        .chain(untracked.lines())
+        .filter(|v| !v.is_empty())
+        .map(|v| root.join(v))
+        .collect())
+}
+
+fn git(args: &[&str]) -> Result<String> {
+    let output = std::process::Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| anyhow!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+// removes "." and ".." without touching the file system, patterns are no paths
+// that exist
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            | Component::CurDir => {},
+            | Component::ParentDir => {
+                normalized.pop();
+            },
+            | v => normalized.push(v),
+        }
+    }
+    normalized
+}
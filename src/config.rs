@@ -1,11 +1,658 @@
-#[derive(serde::Deserialize)]
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+use crate::{
+    cache::CacheSpec,
+    format::Format,
+    import::Importer,
+    logs::LogMode,
+    multiplexer::TaskDefinition,
+    parallelism::Parallelism,
+    template::{self, Context},
+};
+
+pub const DISCOVERY_NAMES: [&str; 6] = [
+    "bobr.toml",
+    "bobr.yaml",
+    "bobr.yml",
+    "bobr.json",
+    "bobr.json5",
+    "bobr.ron",
+];
+
+// unknown fields are ignored when loading, so configs written for newer
+// versions keep working. The schema rejects them and `validate` reports them.
+#[derive(serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case")]
+#[schemars(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default, alias = "settings")]
+    pub defaults: Defaults,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    #[serde(default)]
     pub commands: Vec<Command>,
 }
 
-#[derive(serde::Deserialize)]
+impl Config {
+    // "-" reads the config from stdin, without a format it is inferred from the
+    // path and the content. Files of other tools (like a Procfile) are imported.
+    // With `check` it also returns the fields that loading ignores because they
+    // are not part of the schema
+    pub fn load(
+        path: &Path,
+        format: Option<Format>,
+        make_targets: &[String],
+        check: bool,
+    ) -> Result<(Self, Vec<String>)> {
+        let content = match path.to_str() {
+            | Some("-") => std::io::read_to_string(std::io::stdin())?,
+            | _ => std::fs::read_to_string(path)?,
+        };
+        let format = match (format, Importer::from_path(path)) {
+            | (Some(format), _) => format,
+            | (None, Some(importer)) => return Ok((importer.import(path, &content, make_targets)?, Vec::new())),
+            | (None, None) => Format::detect(path, &content)?,
+        };
+        let config = format.parse_config(&content)?;
+        // RON structs can not be read without knowing their type, so its unknown
+        // fields are not reported
+        let value = match check {
+            | true => format.parse::<serde_json::Value>(&content).ok(),
+            | false => None,
+        };
+        let unknown = match value {
+            | Some(value) => Self::unknown_fields(&value)?,
+            | None => Vec::new(),
+        };
+        Ok((config, unknown))
+    }
+
+    pub fn schema() -> schemars::schema::RootSchema {
+        let mut schema = schemars::schema_for!(Config);
+        // the derived schema leaves out aliases, so `[settings]` would be rejected
+        let properties = &mut schema.schema.object().properties;
+        if let Some(defaults) = properties.get("defaults").cloned() {
+            properties.insert("settings".to_owned(), defaults);
+        }
+        schema
+    }
+
+    // the paths of the fields the schema does not know, like "commands[0].nmae"
+    fn unknown_fields(value: &serde_json::Value) -> Result<Vec<String>> {
+        let schema = serde_json::to_value(Self::schema())?;
+        let mut fields = Vec::new();
+        Self::collect_unknown_fields(value, &schema, &schema["definitions"], "", &mut fields);
+        Ok(fields)
+    }
+
+    fn collect_unknown_fields(
+        value: &serde_json::Value,
+        schema: &serde_json::Value,
+        definitions: &serde_json::Value,
+        path: &str,
+        fields: &mut Vec<String>,
+    ) {
+        if let Some(name) = schema["$ref"].as_str().and_then(|v| v.strip_prefix("#/definitions/")) {
+            Self::collect_unknown_fields(value, &definitions[name], definitions, path, fields);
+        }
+        for schema in ["allOf", "anyOf", "oneOf"]
+            .iter()
+            .filter_map(|v| schema[v].as_array())
+            .flatten()
+        {
+            Self::collect_unknown_fields(value, schema, definitions, path, fields);
+        }
+        match (value, schema["properties"].as_object()) {
+            | (serde_json::Value::Object(object), Some(properties)) => {
+                for (key, value) in object {
+                    let path = match path {
+                        | "" => key.clone(),
+                        | _ => format!("{}.{}", path, key),
+                    };
+                    match properties.get(key) {
+                        | Some(schema) => Self::collect_unknown_fields(value, schema, definitions, &path, fields),
+                        | None if schema["additionalProperties"] == false => fields.push(path),
+                        | None => {},
+                    }
+                }
+            },
+            | (serde_json::Value::Array(items), _) if schema["items"].is_object() => {
+                for (i, value) in items.iter().enumerate() {
+                    let path = format!("{}[{}]", path, i);
+                    Self::collect_unknown_fields(value, &schema["items"], definitions, &path, fields);
+                }
+            },
+            | _ => {},
+        }
+    }
+
+    // looks in the given directory and then in its parents, like cargo does. A
+    // file whose format is not enabled is an error instead of being passed over.
+    pub fn discover(start: &Path) -> Result<Option<PathBuf>> {
+        let found = start.ancestors().find_map(|dir| {
+            DISCOVERY_NAMES
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|path| path.is_file())
+        });
+        if let Some(path) = &found {
+            Format::from_path(path)?;
+        }
+        Ok(found)
+    }
+}
+
+// relative paths in a config file are relative to the directory of that file
+pub fn resolve_path(config: &Path, path: &str) -> PathBuf {
+    match config.parent() {
+        | Some(dir) => dir.join(path),
+        | None => PathBuf::from(path),
+    }
+}
+
+pub fn split_program(program: &str) -> Result<Vec<String>> {
+    let program = program.split_whitespace().map(|v| v.to_owned()).collect::<Vec<_>>();
+    if program.is_empty() {
+        return Err(anyhow!("the program must not be empty"));
+    }
+    Ok(program)
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+#[schemars(deny_unknown_fields)]
+pub struct Defaults {
+    pub program: Option<String>,
+    pub stderr: Option<usize>,
+    pub stdout: Option<String>,
+    pub parallelism: Option<Parallelism>,
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    // whether runs are stored in the history, see --no-history
+    pub history: Option<bool>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema, Default)]
 #[serde(rename_all = "snake_case")]
+#[schemars(deny_unknown_fields)]
 pub struct Command {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub program: Option<String>,
     pub command: String,
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub log: Option<LogMode>,
+    // keeps the order of the file, which is used for the task names
+    #[serde(default)]
+    pub matrix: IndexMap<String, Vec<String>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    // glob patterns of the files the command depends on, see --changed-since
+    #[serde(default)]
+    pub paths: Vec<String>,
+    // commands declaring inputs are skipped if a successful run with the same
+    // inputs, outputs, argv, cwd and input_env is cached
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    #[serde(default)]
+    pub outputs: Vec<String>,
+    #[serde(default)]
+    pub input_env: Vec<String>,
+}
+
+// settings an including file passes on to the files it includes
+#[derive(Default, Clone)]
+struct Scope {
+    namespace: Option<String>,
+    program: Option<Vec<String>>,
+    env: BTreeMap<String, String>,
+    vars: BTreeMap<String, String>,
+    included: bool,
+    // found by Config::discover, possibly in a parent of the current directory
+    discovered: bool,
+}
+
+// resolves config files (and the files they include) into task definitions
+pub struct Loader {
+    program: Vec<String>,
+    program_explicit: bool,
+    vars: BTreeMap<String, String>,
+    format: Option<Format>,
+    make_targets: Vec<String>,
+    stack: Vec<PathBuf>,
+    names: BTreeMap<String, PathBuf>,
+    // the namespace of every command, dependencies are looked up in it first
+    namespaces: Vec<Option<String>>,
+    commands: Vec<TaskDefinition>,
+    // only collected by `validate`
+    unknown_fields: Option<Vec<String>>,
+}
+
+impl Loader {
+    // vars given here override the vars of all config files
+    pub fn new(program: Vec<String>, program_explicit: bool, vars: BTreeMap<String, String>) -> Self {
+        Self {
+            program,
+            program_explicit,
+            vars,
+            format: None,
+            make_targets: Vec::new(),
+            stack: Vec::new(),
+            names: BTreeMap::new(),
+            namespaces: Vec::new(),
+            commands: Vec::new(),
+            unknown_fields: None,
+        }
+    }
+
+    // overrides the format of top-level config files, included files are always
+    // detected
+    pub fn with_format(mut self, format: Option<Format>) -> Self {
+        self.format = format;
+        self
+    }
+
+    // the targets imported from top-level Makefiles, all .PHONY targets if empty
+    pub fn with_make_targets(mut self, make_targets: Vec<String>) -> Self {
+        self.make_targets = make_targets;
+        self
+    }
+
+    // loads a top-level config file and returns its defaults for the run-wide
+    // settings
+    pub fn load(&mut self, path: &Path) -> Result<Defaults> {
+        self.load_scoped(path, &Scope::default())
+    }
+
+    // loads a file found by Config::discover
+    pub fn load_discovered(&mut self, path: &Path) -> Result<Defaults> {
+        self.load_scoped(path, &Scope {
+            discovered: true,
+            ..Default::default()
+        })
+    }
+
+    fn load_scoped(&mut self, path: &Path, scope: &Scope) -> Result<Defaults> {
+        let canonical = match path.to_str() {
+            | Some("-") => path.to_owned(),
+            | _ => {
+                std::fs::canonicalize(path)
+                    .map_err(|e| anyhow!("failed to load config file \"{}\": {}", path.display(), e))?
+            },
+        };
+        if self.stack.contains(&canonical) {
+            return Err(anyhow!(
+                "include cycle detected: {} -> {}",
+                self.stack.iter().map(|v| v.display()).join(" -> "),
+                canonical.display()
+            ));
+        }
+        let format = self.format.filter(|_| !scope.included);
+        let make_targets = match scope.included {
+            | true => &[][..],
+            | false => &self.make_targets[..],
+        };
+        let (config, unknown_fields) = Config::load(path, format, make_targets, self.unknown_fields.is_some())
+            .map_err(|e| anyhow!("failed to load config file \"{}\": {}", path.display(), e))?;
+        if let Some(fields) = self.unknown_fields.as_mut() {
+            fields.extend(
+                unknown_fields
+                    .into_iter()
+                    .map(|v| format!("unknown field \"{}\" (in \"{}\")", v, path.display())),
+            );
+        }
+        let defaults = config.defaults;
+
+        let mut vars = scope.vars.clone();
+        vars.extend(config.vars);
+        vars.extend(self.vars.clone());
+        let render =
+            |value: &str, env: Option<&BTreeMap<String, String>>, matrix: Option<&BTreeMap<String, String>>| {
+                let context = Context {
+                    vars: Some(&vars),
+                    matrix,
+                    env,
+                };
+                template::render(value, &context).map_err(|e| anyhow!("{} (in \"{}\")", e, path.display()))
+            };
+
+        let program = match (&defaults.program, &scope.program) {
+            | _ if self.program_explicit => self.program.clone(),
+            | (Some(v), _) => {
+                split_program(&render(v, None, None)?).map_err(|e| anyhow!("{} (in \"{}\")", e, path.display()))?
+            },
+            | (None, Some(v)) => v.clone(),
+            | (None, None) => self.program.clone(),
+        };
+        let mut env = scope.env.clone();
+        for (key, value) in &defaults.env {
+            env.insert(key.clone(), render(value, None, None)?);
+        }
+        // commands of included and discovered files run in the directory of their
+        // file by default
+        let cwd = match (&defaults.cwd, scope.included || scope.discovered) {
+            | (Some(v), _) => Some(render(v, Some(&env), None)?),
+            | (None, true) => Some(".".to_owned()),
+            | (None, false) => None,
+        };
+
+        for (i, v) in config.commands.into_iter().enumerate() {
+            for combination in
+                Self::expand_matrix(&v.matrix).map_err(|e| anyhow!("{} (in \"{}\")", e, path.display()))?
+            {
+                let matrix = combination.iter().cloned().collect::<BTreeMap<_, _>>();
+                let mut task_env = env.clone();
+                for (key, value) in &combination {
+                    task_env.insert(Self::matrix_env(key), value.clone());
+                }
+                for (key, value) in &v.env {
+                    task_env.insert(key.clone(), render(value, None, Some(&matrix))?);
+                }
+                let render = |value: &str| render(value, Some(&task_env), Some(&matrix));
+
+                // tasks expanded from a matrix are named like
+                // "build[target=x86_64,profile=dev]"
+                let suffix = match combination.is_empty() {
+                    | true => String::new(),
+                    | false => {
+                        format!(
+                            "[{}]",
+                            combination.iter().map(|(k, v)| format!("{}={}", k, v)).join(",")
+                        )
+                    },
+                };
+                let name = match (&scope.namespace, v.name.as_deref().map(render).transpose()?) {
+                    | (Some(ns), Some(name)) => Some(format!("{}/{}{}", ns, name, suffix)),
+                    | (Some(ns), None) => Some(format!("{}/{}{}", ns, i, suffix)),
+                    | (None, Some(name)) => Some(format!("{}{}", name, suffix)),
+                    | (None, None) if !suffix.is_empty() => Some(format!("{}{}", i, suffix)),
+                    | (None, None) => None,
+                };
+                if let Some(name) = &name {
+                    if let Some(other) = self.names.insert(name.clone(), path.to_owned()) {
+                        return Err(anyhow!(
+                            "duplicate task name \"{}\" (defined in \"{}\" and \"{}\")",
+                            name,
+                            other.display(),
+                            path.display()
+                        ));
+                    }
+                }
+                let program = match &v.program {
+                    | Some(v) => {
+                        split_program(&render(v)?).map_err(|e| anyhow!("{} (in \"{}\")", e, path.display()))?
+                    },
+                    | None => program.clone(),
+                };
+                let cwd = match &v.cwd {
+                    | Some(v) => Some(render(v)?),
+                    | None => cwd.clone(),
+                };
+                let command = render(&v.command)?;
+                let description = v.description.as_deref().map(render).transpose()?;
+                let tags = v.tags.iter().map(|v| render(v)).collect::<Result<_>>()?;
+                let depends_on = v.depends_on.iter().map(|v| render(v)).collect::<Result<_>>()?;
+                let resolve = |values: &[String]| {
+                    values
+                        .iter()
+                        .map(|v| Ok(resolve_path(path, &render(v)?)))
+                        .collect::<Result<Vec<_>>>()
+                };
+                let paths = resolve(&v.paths)?;
+                // cached paths are absolute, so the records do not depend on the directory
+                // bobr is started in
+                let absolute = |values: &[String]| {
+                    resolve(values)?
+                        .into_iter()
+                        .map(|v| Ok(std::path::absolute(v)?))
+                        .collect::<Result<_>>()
+                };
+                let cache = match v.inputs.is_empty() {
+                    | true => None,
+                    | false => {
+                        Some(CacheSpec {
+                            inputs: absolute(&v.inputs)?,
+                            outputs: absolute(&v.outputs)?,
+                            env: v.input_env.clone(),
+                        })
+                    },
+                };
+                self.namespaces.push(scope.namespace.clone());
+                self.commands.push(TaskDefinition {
+                    name,
+                    description,
+                    program,
+                    command,
+                    cwd: cwd.map(|v| resolve_path(path, &v)),
+                    env: task_env,
+                    log: v.log,
+                    tags,
+                    depends_on,
+                    order_only: false,
+                    source: Some(path.to_owned()),
+                    paths,
+                    skip: None,
+                    cache,
+                });
+            }
+        }
+
+        self.stack.push(canonical);
+        for include in Self::resolve_includes(path, &config.include)? {
+            let scope = Scope {
+                namespace: Some(
+                    scope
+                        .namespace
+                        .iter()
+                        .cloned()
+                        .chain([Self::namespace(path, &include)])
+                        .join("/"),
+                ),
+                program: Some(program.clone()),
+                env: env.clone(),
+                vars: vars.clone(),
+                included: true,
+                discovered: false,
+            };
+            self.load_scoped(&include, &scope)?;
+        }
+        self.stack.pop();
+
+        Ok(defaults)
+    }
+
+    // resolves the dependencies of all loaded commands to task names and returns
+    // the commands
+    pub fn finish(mut self) -> Result<Vec<TaskDefinition>> {
+        for (task, namespace) in self.commands.iter_mut().zip(&self.namespaces) {
+            let mut resolved = Vec::<String>::new();
+            for dependency in &task.depends_on {
+                // a name in the namespace of the command shadows a global one
+                let candidates = namespace
+                    .iter()
+                    .map(|ns| format!("{}/{}", ns, dependency))
+                    .chain([dependency.clone()]);
+                let names = candidates
+                    .map(|v| Self::matching_names(&self.names, &v))
+                    .find(|v| !v.is_empty())
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "unknown dependency \"{}\" of task \"{}\"",
+                            dependency,
+                            task.name.as_deref().unwrap_or(task.command.trim())
+                        )
+                    })?;
+                resolved.extend(names);
+            }
+            task.depends_on = resolved;
+        }
+
+        let dependencies = self
+            .commands
+            .iter()
+            .filter_map(|v| Some((v.name.clone()?, v.depends_on.clone())))
+            .collect::<BTreeMap<_, _>>();
+        let mut checked = BTreeSet::<String>::new();
+        for name in dependencies.keys() {
+            Self::check_cycle(&dependencies, &mut vec![name.clone()], &mut checked)?;
+        }
+        Ok(self.commands)
+    }
+
+    // a name refers to the task itself or to all tasks expanded from it by a matrix
+    fn matching_names(names: &BTreeMap<String, PathBuf>, name: &str) -> Vec<String> {
+        let prefix = format!("{}[", name);
+        names
+            .keys()
+            .filter(|v| *v == name || v.starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn check_cycle(
+        dependencies: &BTreeMap<String, Vec<String>>,
+        path: &mut Vec<String>,
+        checked: &mut BTreeSet<String>,
+    ) -> Result<()> {
+        let name = path.last().unwrap().clone();
+        if checked.contains(&name) {
+            return Ok(());
+        }
+        for dependency in dependencies.get(&name).into_iter().flatten() {
+            if path.contains(dependency) {
+                return Err(anyhow!(
+                    "dependency cycle detected: {} -> {}",
+                    path.join(" -> "),
+                    dependency
+                ));
+            }
+            path.push(dependency.clone());
+            Self::check_cycle(dependencies, path, checked)?;
+            path.pop();
+        }
+        checked.insert(name);
+        Ok(())
+    }
+
+    // loads a config file on its own and checks what would otherwise only fail
+    // when running it
+    pub fn validate(path: &Path) -> Result<Vec<TaskDefinition>> {
+        let mut loader = Self::new(split_program("/bin/sh -c")?, false, BTreeMap::new());
+        loader.unknown_fields = Some(Vec::new());
+        loader.load(path)?;
+        let unknown_fields = loader.unknown_fields.take().unwrap_or_default();
+        if !unknown_fields.is_empty() {
+            return Err(anyhow!("{}", unknown_fields.join(", ")));
+        }
+        let commands = loader.finish()?;
+        for task in &commands {
+            if let Some(cwd) = task.cwd.as_ref().filter(|v| !v.is_dir()) {
+                return Err(anyhow!(
+                    "working directory \"{}\" of task \"{}\" does not exist",
+                    cwd.display(),
+                    task.name.as_deref().unwrap_or(task.command.trim())
+                ));
+            }
+        }
+        Ok(commands)
+    }
+
+    // every combination of the matrix values, a command without matrix results in
+    // a single empty combination
+    fn expand_matrix(matrix: &IndexMap<String, Vec<String>>) -> Result<Vec<Vec<(String, String)>>> {
+        if let Some((key, _)) = matrix.iter().find(|(_, values)| values.is_empty()) {
+            return Err(anyhow!("matrix entry \"{}\" has no values", key));
+        }
+        Ok(matrix.iter().fold(vec![Vec::new()], |combinations, (key, values)| {
+            combinations
+                .into_iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.push((key.clone(), value.clone()));
+                        combination
+                    })
+                })
+                .collect()
+        }))
+    }
+
+    // the environment variable a matrix value is exposed as, like
+    // BOBR_MATRIX_TARGET
+    fn matrix_env(key: &str) -> String {
+        let key = key
+            .chars()
+            .map(|c| {
+                match c {
+                    | c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+                    | _ => '_',
+                }
+            })
+            .collect::<String>();
+        format!("BOBR_MATRIX_{}", key)
+    }
+
+    // expands the include patterns of a file, sorted per pattern for a
+    // deterministic order
+    fn resolve_includes(path: &Path, patterns: &[String]) -> Result<Vec<PathBuf>> {
+        let mut includes = Vec::<PathBuf>::new();
+        for pattern in patterns {
+            let full = resolve_path(path, pattern);
+            let full = full.to_string_lossy();
+            let mut matches = glob::glob(&full)
+                .map_err(|e| anyhow!("invalid include pattern \"{}\": {}", pattern, e))?
+                .collect::<Result<Vec<_>, _>>()?;
+            matches.sort();
+            // a pattern without wildcards refers to exactly one file which must exist
+            if matches.is_empty() && glob::Pattern::escape(pattern) == *pattern {
+                return Err(anyhow!(
+                    "included config file \"{}\" does not exist (included from \"{}\")",
+                    full,
+                    path.display()
+                ));
+            }
+            includes.append(&mut matches);
+        }
+        Ok(includes)
+    }
+
+    // the path of the included file relative to the including one without
+    // extension, or just its directory for files named like the discovered ones
+    // (bobr.toml)
+    fn namespace(path: &Path, include: &Path) -> String {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let relative = include.strip_prefix(dir).unwrap_or(include);
+        let stem = relative.with_extension("");
+        let namespace = match stem.file_name() {
+            | Some(name) if name == "bobr" => stem.parent().unwrap_or(Path::new("")),
+            | _ => stem.as_path(),
+        };
+        let namespace = namespace
+            .components()
+            .filter_map(|v| {
+                match v {
+                    | Component::Normal(v) => Some(v.to_string_lossy()),
+                    | _ => None,
+                }
+            })
+            .join("/");
+        match namespace.is_empty() {
+            | true => "bobr".to_owned(),
+            | false => namespace,
+        }
+    }
 }
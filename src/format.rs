@@ -0,0 +1,194 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+
+use crate::{config::Config, multiplexer::MultiplexerResult};
+
+// every format is enabled by its own "format+<name>" cargo feature. Adding a
+// format means adding a variant here, to `ALL` and its arms in `descriptor`,
+// `parse` and `serialize_result`. The arguments are matched along with the
+// format, so builds without any format have no unused variables.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Format {
+    #[cfg(feature = "format+json")]
+    Json,
+    #[cfg(feature = "format+yaml")]
+    Yaml,
+    #[cfg(feature = "format+toml")]
+    Toml,
+    #[cfg(feature = "format+json5")]
+    Json5,
+    #[cfg(feature = "format+ron")]
+    Ron,
+}
+
+struct Descriptor {
+    name: &'static str,
+    extensions: &'static [&'static str],
+}
+
+impl Format {
+    // in the order content sniffing tries them
+    pub const ALL: &'static [Self] = &[
+        #[cfg(feature = "format+json")]
+        Self::Json,
+        #[cfg(feature = "format+yaml")]
+        Self::Yaml,
+        #[cfg(feature = "format+toml")]
+        Self::Toml,
+        #[cfg(feature = "format+json5")]
+        Self::Json5,
+        #[cfg(feature = "format+ron")]
+        Self::Ron,
+    ];
+
+    fn descriptor(self) -> Descriptor {
+        match self {
+            #[cfg(feature = "format+json")]
+            | Self::Json => {
+                Descriptor {
+                    name: "json",
+                    extensions: &["json"],
+                }
+            },
+            #[cfg(feature = "format+yaml")]
+            | Self::Yaml => {
+                Descriptor {
+                    name: "yaml",
+                    extensions: &["yaml", "yml"],
+                }
+            },
+            #[cfg(feature = "format+toml")]
+            | Self::Toml => {
+                Descriptor {
+                    name: "toml",
+                    extensions: &["toml"],
+                }
+            },
+            #[cfg(feature = "format+json5")]
+            | Self::Json5 => {
+                Descriptor {
+                    name: "json5",
+                    extensions: &["json5"],
+                }
+            },
+            #[cfg(feature = "format+ron")]
+            | Self::Ron => {
+                Descriptor {
+                    name: "ron",
+                    extensions: &["ron"],
+                }
+            },
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.descriptor().name
+    }
+
+    pub fn extensions(self) -> &'static [&'static str] {
+        self.descriptor().extensions
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        Self::ALL.iter().map(|v| v.name()).collect()
+    }
+
+    // lists what is supported, for error messages
+    fn supported() -> String {
+        format!(
+            "supported extensions: {}; enabled features: {}",
+            Self::ALL.iter().flat_map(|v| v.extensions()).join(", "),
+            Self::ALL.iter().map(|v| format!("format+{}", v.name())).join(", ")
+        )
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|v| v.name() == name)
+            .ok_or_else(|| anyhow!("unsupported format \"{}\" ({})", name, Self::supported()))
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path.extension().and_then(|v| v.to_str()).unwrap_or_default();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|v| v.extensions().contains(&extension))
+            .ok_or_else(|| anyhow!("unsupported file format \"{}\" ({})", path.display(), Self::supported()))
+    }
+
+    // the format of a config file by its extension, or else the first format that
+    // can parse its content
+    pub fn detect(path: &Path, content: &str) -> Result<Self> {
+        if let Ok(format) = Self::from_path(path) {
+            return Ok(format);
+        }
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|v| v.parse_config(content).is_ok())
+            .ok_or_else(|| {
+                anyhow!(
+                    "could not detect the format of \"{}\", set it with --format ({})",
+                    path.display(),
+                    Self::supported()
+                )
+            })
+    }
+
+    pub fn parse<T: serde::de::DeserializeOwned>(self, content: &str) -> Result<T> {
+        match (self, content) {
+            #[cfg(feature = "format+json")]
+            | (Self::Json, content) => Ok(serde_json::from_str::<T>(content)?),
+            #[cfg(feature = "format+yaml")]
+            | (Self::Yaml, content) => Ok(serde_yml::from_str::<T>(content)?),
+            #[cfg(feature = "format+toml")]
+            | (Self::Toml, content) => Ok(toml::from_str::<T>(content)?),
+            #[cfg(feature = "format+json5")]
+            | (Self::Json5, content) => Ok(json5::from_str::<T>(content)?),
+            // optional fields can be given without wrapping them in Some(..)
+            #[cfg(feature = "format+ron")]
+            | (Self::Ron, content) => {
+                Ok(ron::Options::default()
+                    .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+                    .from_str::<T>(content)?)
+            },
+        }
+    }
+
+    pub fn parse_config(self, content: &str) -> Result<Config> {
+        self.parse::<Config>(content)
+    }
+
+    pub fn serialize_result(self, result: &MultiplexerResult) -> Result<String> {
+        match (self, result) {
+            #[cfg(feature = "format+json")]
+            | (Self::Json, result) => Ok(serde_json::to_string(result)?),
+            #[cfg(feature = "format+yaml")]
+            | (Self::Yaml, result) => Ok(serde_yml::to_string(result)?),
+            #[cfg(feature = "format+toml")]
+            | (Self::Toml, result) => result.to_toml(),
+            // there is no JSON5 serializer, the result is written as plain JSON,
+            // which is valid JSON5
+            #[cfg(feature = "format+json5")]
+            | (Self::Json5, result) => Ok(serde_json::to_string(result)?),
+            #[cfg(feature = "format+ron")]
+            | (Self::Ron, result) => Ok(ron::ser::to_string_pretty(result, ron::ser::PrettyConfig::default())?),
+        }
+    }
+}
+
+// writes to a temporary file first, so readers never observe a partial file
+pub fn write_atomic(path: &Path, content: &str) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid output path \"{}\"", path.display()))?;
+    let tmp = path.with_file_name(format!(".{}.{}.tmp", file_name.to_string_lossy(), std::process::id()));
+    std::fs::write(&tmp, content)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
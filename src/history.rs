@@ -0,0 +1,329 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{Duration, SecondsFormat};
+use itertools::Itertools;
+
+use crate::{
+    format,
+    map::quote,
+    multiplexer::{
+        MultiplexerResult, MultiplexerResultDataTask, MultiplexerResultMetadata, MultiplexerResultTaskStatus,
+        TaskDefinition,
+    },
+    table,
+};
+
+// older runs are removed when a new one is stored
+const RETAINED_RUNS: usize = 100;
+
+// the latest duration of every task by the directory of the run, the task name
+// and its command, kept apart from the runs so estimating does not load them
+// with all their output
+type Durations = BTreeMap<PathBuf, BTreeMap<String, BTreeMap<String, RecordedDuration>>>;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct RecordedDuration {
+    // the run it was recorded in, it is dropped along with the run
+    run: String,
+    duration_ms: i64,
+}
+
+// a run as stored in the history, ids sort in the order the runs started
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Run {
+    pub id: String,
+    // the directory and arguments bobr was started with
+    pub cwd: PathBuf,
+    pub args: Vec<String>,
+    pub metadata: MultiplexerResultMetadata,
+    pub tasks: BTreeMap<usize, RunTask>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct RunTask {
+    #[serde(flatten)]
+    pub task: MultiplexerResultDataTask,
+    pub duration_ms: Option<i64>,
+    // left out of the result, see MultiplexerResultDataTask
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl Run {
+    pub fn new(id: String, args: Vec<String>, result: MultiplexerResult) -> Result<Self> {
+        Ok(Self {
+            id,
+            cwd: std::env::current_dir()?,
+            args,
+            metadata: result.metadata,
+            tasks: result
+                .tasks
+                .into_iter()
+                .map(|(id, mut task)| {
+                    let duration_ms = task.duration().map(|v| v.num_milliseconds());
+                    let env = std::mem::take(&mut task.env);
+                    (id, RunTask { task, duration_ms, env })
+                })
+                .collect(),
+        })
+    }
+
+    fn status(&self) -> &'static str {
+        let failed = self.tasks.values().any(|v| {
+            matches!(
+                v.task.status,
+                MultiplexerResultTaskStatus::Failed | MultiplexerResultTaskStatus::Cancelled
+            )
+        });
+        match (self.metadata.interrupted, failed) {
+            | (true, _) => "interrupted",
+            | (false, true) => "failed",
+            | (false, false) => "success",
+        }
+    }
+
+    // the tasks with their outcome, followed by the output of the tasks that did
+    // not succeed (or of all tasks)
+    pub fn write(&self, writer: &mut dyn Write, logs: bool) -> Result<()> {
+        writeln!(
+            writer,
+            "run {} ({}), started {}, took {}",
+            self.id,
+            self.status(),
+            self.metadata.started.to_rfc3339_opts(SecondsFormat::Secs, true),
+            seconds(Some((self.metadata.ended - self.metadata.started).num_milliseconds()))
+        )?;
+        writeln!(writer, "cwd:  {}", self.cwd.display())?;
+        writeln!(writer, "args: {}", self.args.iter().map(|v| quote(v)).join(" "))?;
+        writeln!(writer)?;
+
+        let rows = [["ID", "NAME", "STATUS", "EXIT CODE", "DURATION"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect()]
+        .into_iter()
+        .chain(self.tasks.iter().map(|(id, v)| {
+            vec![
+                id.to_string(),
+                v.task.name.clone(),
+                status_name(&v.task),
+                v.task
+                    .exit_code
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| "-".to_owned()),
+                seconds(v.duration_ms),
+            ]
+        }))
+        .collect::<Vec<_>>();
+        for line in table::align(&rows) {
+            writeln!(writer, "{}", line)?;
+        }
+
+        for v in self.tasks.values() {
+            let failed = v.task.status == MultiplexerResultTaskStatus::Failed;
+            if logs && !v.task.stdout.is_empty() {
+                writeln!(writer, "\n==> {} (stdout) <==", v.task.name)?;
+                write!(writer, "{}", v.task.stdout)?;
+            }
+            if (logs || failed) && !v.task.stderr.is_empty() {
+                writeln!(writer, "\n==> {} (stderr) <==", v.task.name)?;
+                write!(writer, "{}", v.task.stderr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn status_name(task: &MultiplexerResultDataTask) -> String {
+    match task.cached {
+        | true => "cached".to_owned(),
+        | false => format!("{:?}", task.status).to_lowercase(),
+    }
+}
+
+fn seconds(ms: Option<i64>) -> String {
+    match ms {
+        | Some(v) => format!("{:.1}s", v as f64 / 1000.0),
+        | None => "-".to_owned(),
+    }
+}
+
+// $XDG_STATE_HOME/bobr, falling back to its default ~/.local/state/bobr
+pub fn dir() -> Result<PathBuf> {
+    match std::env::var_os("XDG_STATE_HOME").filter(|v| !v.is_empty()) {
+        | Some(v) => Ok(PathBuf::from(v).join("bobr")),
+        | None => {
+            std::env::var_os("HOME")
+                .map(|v| PathBuf::from(v).join(".local/state/bobr"))
+                .ok_or_else(|| anyhow!("can not locate the history, neither XDG_STATE_HOME nor HOME is set"))
+        },
+    }
+}
+
+fn runs_dir() -> Result<PathBuf> {
+    Ok(dir()?.join("runs"))
+}
+
+fn durations_path() -> Result<PathBuf> {
+    Ok(dir()?.join("durations.json"))
+}
+
+// held while the index is updated, so concurrent runs do not drop each
+// other's durations
+fn lock_durations() -> Result<std::fs::File> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir()?.join("durations.lock"))?;
+    file.lock()?;
+    Ok(file)
+}
+
+fn load_durations() -> Result<Durations> {
+    match std::fs::read_to_string(durations_path()?) {
+        | Ok(v) => Ok(serde_json::from_str::<Durations>(&v)?),
+        | Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Durations::new()),
+        | Err(e) => Err(e.into()),
+    }
+}
+
+// a new id, derived from the start of the run. The process id tells apart runs
+// started at the same time.
+pub fn id(result: &MultiplexerResult) -> String {
+    format!(
+        "{}-{}",
+        result.metadata.started.format("%Y%m%d-%H%M%S%.3f"),
+        std::process::id()
+    )
+}
+
+pub fn store(run: &Run) -> Result<()> {
+    let dir = runs_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    format::write_atomic(&dir.join(format!("{}.json", run.id)), &serde_json::to_string(run)?)?;
+    for id in ids(&dir)?.iter().rev().skip(RETAINED_RUNS) {
+        std::fs::remove_file(dir.join(format!("{}.json", id)))?;
+    }
+
+    let _lock = lock_durations()?;
+    // an unreadable index is started over
+    let mut durations = load_durations().unwrap_or_default();
+    let names = durations.entry(run.cwd.clone()).or_default();
+    for v in run.tasks.values().filter(|v| !v.task.cached) {
+        if let Some(duration_ms) = v.duration_ms {
+            names
+                .entry(v.task.name.clone())
+                .or_default()
+                .insert(v.task.command.clone(), RecordedDuration {
+                    run: run.id.clone(),
+                    duration_ms,
+                });
+        }
+    }
+    let retained = ids(&dir)?;
+    for names in durations.values_mut() {
+        for commands in names.values_mut() {
+            commands.retain(|_, v| retained.binary_search(&v.run).is_ok());
+        }
+        names.retain(|_, v| !v.is_empty());
+    }
+    durations.retain(|_, v| !v.is_empty());
+    format::write_atomic(&durations_path()?, &serde_json::to_string(&durations)?)
+}
+
+// the ids of the stored runs, oldest first
+fn ids(dir: &Path) -> Result<Vec<String>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut ids = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|v| v == "json") {
+            if let Some(stem) = path.file_stem().and_then(|v| v.to_str()) {
+                ids.push(stem.to_owned());
+            }
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+// the run with the given id, or the latest one
+pub fn load(id: Option<&str>) -> Result<Run> {
+    let dir = runs_dir()?;
+    let id = match id {
+        | Some(v) => v.to_owned(),
+        | None => ids(&dir)?.pop().ok_or_else(|| anyhow!("no runs recorded yet"))?,
+    };
+    let content = match std::fs::read_to_string(dir.join(format!("{}.json", id))) {
+        | Ok(v) => v,
+        | Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(anyhow!("unknown run \"{}\"", id)),
+        | Err(e) => return Err(e.into()),
+    };
+    Ok(serde_json::from_str::<Run>(&content)?)
+}
+
+// the duration of every task in its latest run that ran it, by task id. Tasks
+// are recognized by their name and command within runs started in the same
+// directory.
+pub fn estimates(tasks: &[TaskDefinition]) -> Result<BTreeMap<usize, Duration>> {
+    let mut durations = load_durations()?;
+    let names = durations.remove(&std::env::current_dir()?).unwrap_or_default();
+    Ok(tasks
+        .iter()
+        .enumerate()
+        .filter_map(|(i, v)| {
+            let name = v.name.clone().unwrap_or_else(|| i.to_string());
+            names
+                .get(&name)
+                .and_then(|commands| commands.get(&v.command))
+                .map(|v| (i, Duration::milliseconds(v.duration_ms)))
+        })
+        .collect())
+}
+
+// the latest runs, newest first
+pub fn write_list(writer: &mut dyn Write, limit: usize) -> Result<()> {
+    let dir = runs_dir()?;
+    let mut rows = vec![["ID", "STATUS", "STARTED", "DURATION", "TASKS", "FAILED", "CWD"]
+        .iter()
+        .map(|v| v.to_string())
+        .collect_vec()];
+    for id in ids(&dir)?.iter().rev().take(limit) {
+        // runs written by other versions are listed without details
+        let run = match load(Some(id)) {
+            | Ok(v) => v,
+            | Err(_) => {
+                rows.push(vec![id.clone(), "unreadable".to_owned()]);
+                continue;
+            },
+        };
+        rows.push(vec![
+            run.id.clone(),
+            run.status().to_owned(),
+            run.metadata.started.to_rfc3339_opts(SecondsFormat::Secs, true),
+            seconds(Some((run.metadata.ended - run.metadata.started).num_milliseconds())),
+            run.tasks.len().to_string(),
+            run.tasks
+                .values()
+                .filter(|v| v.task.status == MultiplexerResultTaskStatus::Failed)
+                .count()
+                .to_string(),
+            run.cwd.display().to_string(),
+        ]);
+    }
+    for line in table::align(&rows) {
+        writeln!(writer, "{}", line)?;
+    }
+    Ok(())
+}
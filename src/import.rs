@@ -0,0 +1,160 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{anyhow, Result};
+
+use crate::config::{Command, Config, Defaults};
+
+// files of other tools that are turned into config files, recognized by their
+// file name
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Importer {
+    // "name: command" per line, like foreman and heroku
+    Procfile,
+    // the "scripts" of a node package
+    PackageJson,
+    // the requested targets, or else the ones declared as .PHONY
+    Makefile,
+}
+
+impl Importer {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        match name {
+            | "package.json" => Some(Self::PackageJson),
+            | "Makefile" | "makefile" | "GNUmakefile" => Some(Self::Makefile),
+            | _ if name == "Procfile" || name.starts_with("Procfile.") => Some(Self::Procfile),
+            | _ => None,
+        }
+    }
+
+    pub fn import(self, path: &Path, content: &str, make_targets: &[String]) -> Result<Config> {
+        let commands = match self {
+            | Self::Procfile => procfile(content)?,
+            | Self::PackageJson => package_json(path, content)?,
+            | Self::Makefile => makefile(path, content, make_targets)?,
+        };
+        if commands.is_empty() {
+            return Err(anyhow!("no tasks found in \"{}\"", path.display()));
+        }
+        Ok(Config {
+            // the tasks run next to the imported file
+            defaults: Defaults {
+                cwd: Some(".".to_owned()),
+                ..Default::default()
+            },
+            include: Vec::new(),
+            vars: BTreeMap::new(),
+            commands,
+        })
+    }
+}
+
+fn task(name: &str, command: String) -> Command {
+    Command {
+        name: Some(name.to_owned()),
+        command,
+        ..Default::default()
+    }
+}
+
+fn procfile(content: &str) -> Result<Vec<Command>> {
+    let mut commands = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, command) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid Procfile entry at line {} (expected \"name: command\")", i + 1))?;
+        commands.push(task(name.trim(), command.trim().to_owned()));
+    }
+    Ok(commands)
+}
+
+// scripts run with the package manager whose lock file is next to the package,
+// pre- and post-scripts are run by the package manager itself
+fn package_json(path: &Path, content: &str) -> Result<Vec<Command>> {
+    #[derive(serde::Deserialize)]
+    struct Package {
+        #[serde(default)]
+        scripts: indexmap::IndexMap<String, String>,
+    }
+    let package = serde_json::from_str::<Package>(content)?;
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let manager = [
+        ("pnpm-lock.yaml", "pnpm"),
+        ("yarn.lock", "yarn"),
+        ("bun.lock", "bun"),
+        ("bun.lockb", "bun"),
+    ]
+    .iter()
+    .find(|(lock, _)| dir.join(lock).is_file())
+    .map(|(_, manager)| *manager)
+    .unwrap_or("npm");
+
+    let hook = |name: &str| {
+        ["pre", "post"].iter().any(|prefix| {
+            name.strip_prefix(prefix)
+                .is_some_and(|script| package.scripts.contains_key(script))
+        })
+    };
+    Ok(package
+        .scripts
+        .keys()
+        .filter(|name| !hook(name))
+        .map(|name| task(name, format!("{} run {}", manager, name)))
+        .collect())
+}
+
+fn makefile(path: &Path, content: &str, requested: &[String]) -> Result<Vec<Command>> {
+    let mut phony = Vec::<String>::new();
+    let mut targets = Vec::<String>::new();
+    // lines ending with a backslash are continued on the next line
+    let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    for line in content.lines() {
+        // recipes are indented with a tab
+        if line.starts_with('\t') && !line.trim_start().starts_with(".PHONY") {
+            continue;
+        }
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let Some((names, rest)) = line.split_once(':') else {
+            continue;
+        };
+        // assignments like "X := y" and "X = a:b" are no rules
+        if names.contains('=') || rest.starts_with('=') || rest.starts_with(":=") {
+            continue;
+        }
+        let names = names.split_whitespace().map(|v| v.to_owned()).collect::<Vec<_>>();
+        match names.as_slice() {
+            | [name] if name == ".PHONY" => {
+                phony.extend(rest.trim_start_matches(':').split_whitespace().map(|v| v.to_owned()))
+            },
+            | _ => targets.extend(names),
+        }
+    }
+
+    let selected = match requested.is_empty() {
+        | true => phony,
+        | false => {
+            for target in requested {
+                if !targets.contains(target) && !phony.contains(target) {
+                    return Err(anyhow!(
+                        "target \"{}\" is not defined in \"{}\"",
+                        target,
+                        path.display()
+                    ));
+                }
+            }
+            requested.to_vec()
+        },
+    };
+    let mut commands = Vec::<Command>::new();
+    for target in selected {
+        if !commands.iter().any(|v| v.name.as_deref() == Some(target.as_str())) {
+            commands.push(task(&target, format!("make {}", target)));
+        }
+    }
+    Ok(commands)
+}
@@ -0,0 +1,41 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::format::Format;
+
+// a commented starter config in the given format (JSON has no comments)
+pub fn starter(format: Format) -> &'static str {
+    match format {
+        #[cfg(feature = "format+json")]
+        | Format::Json => include_str!("init/bobr.json"),
+        #[cfg(feature = "format+yaml")]
+        | Format::Yaml => include_str!("init/bobr.yaml"),
+        #[cfg(feature = "format+toml")]
+        | Format::Toml => include_str!("init/bobr.toml"),
+        #[cfg(feature = "format+json5")]
+        | Format::Json5 => include_str!("init/bobr.json5"),
+        #[cfg(feature = "format+ron")]
+        | Format::Ron => include_str!("init/bobr.ron"),
+    }
+}
+
+// formats with comments are preferred, as the starter explains the options in
+// them
+pub fn default_format() -> Result<Format> {
+    ["toml", "yaml", "json5", "ron", "json"]
+        .iter()
+        .find_map(|v| Format::from_name(v).ok())
+        .ok_or_else(|| anyhow!("no config format enabled"))
+}
+
+pub fn write(path: &Path, format: Format, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        return Err(anyhow!(
+            "\"{}\" already exists (use --force to overwrite it)",
+            path.display()
+        ));
+    }
+    std::fs::write(path, starter(format))?;
+    Ok(())
+}
@@ -1 +1,3 @@
+pub mod cache;
+pub mod logs;
 pub mod multiplexer;
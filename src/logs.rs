@@ -0,0 +1,140 @@
+use std::{fs::File, io::Write, path::PathBuf};
+
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+
+#[derive(serde::Deserialize, schemars::JsonSchema, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogMode {
+    // stdout and stderr are written to separate files
+    Split,
+    // stdout and stderr are interleaved in a single file, prefixed with a timestamp
+    Combined,
+    Off,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogSettings {
+    pub dir: PathBuf,
+    pub max_size: Option<u64>,
+    // the number of rotated generations kept of every file
+    pub max_files: usize,
+}
+
+pub(crate) struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    max_files: usize,
+}
+
+impl LogFile {
+    fn create(path: PathBuf, settings: &LogSettings) -> Result<Self> {
+        Ok(Self {
+            file: File::create(&path)?,
+            path,
+            size: 0,
+            max_size: settings.max_size,
+            max_files: settings.max_files,
+        })
+    }
+
+    fn write(&mut self, content: &str) -> Result<()> {
+        if let Some(max_size) = self.max_size {
+            if self.size > 0 && self.size + content.len() as u64 > max_size {
+                self.rotate()?;
+            }
+        }
+        self.file.write_all(content.as_bytes())?;
+        self.size += content.len() as u64;
+        Ok(())
+    }
+
+    // the previous generations are kept next to the current file as <file>.1
+    // (the latest) up to <file>.<max_files>, older ones are dropped
+    fn rotate(&mut self) -> Result<()> {
+        let generation = |n: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", n));
+            PathBuf::from(path)
+        };
+        for n in (1..self.max_files).rev() {
+            if generation(n).exists() {
+                std::fs::rename(generation(n), generation(n + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            std::fs::rename(&self.path, generation(1))?;
+        }
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+pub(crate) enum TaskLogger {
+    Split { stdout: LogFile, stderr: LogFile },
+    Combined(LogFile),
+}
+
+impl TaskLogger {
+    // the files are prefixed with the task id, as different names can map to the
+    // same file name and names repeat across config files
+    pub fn create(settings: &LogSettings, id: usize, name: &str, mode: LogMode) -> Result<Option<Self>> {
+        let base = format!("{}-{}", id, file_name(name));
+        let path = |suffix: &str| settings.dir.join(format!("{}.{}", base, suffix));
+        Ok(match mode {
+            | LogMode::Split => {
+                Some(Self::Split {
+                    stdout: LogFile::create(path("stdout.log"), settings)?,
+                    stderr: LogFile::create(path("stderr.log"), settings)?,
+                })
+            },
+            | LogMode::Combined => Some(Self::Combined(LogFile::create(path("log"), settings)?)),
+            | LogMode::Off => None,
+        })
+    }
+
+    pub fn stdout(&mut self, content: &str) -> Result<()> {
+        match self {
+            | Self::Split { stdout, .. } => stdout.write(content),
+            | Self::Combined(file) => file.write(&Self::combined_line("stdout", content.trim_end_matches('\n'))),
+        }
+    }
+
+    pub fn stderr(&mut self, line: &str) -> Result<()> {
+        match self {
+            | Self::Split { stderr, .. } => stderr.write(&format!("{}\n", line)),
+            | Self::Combined(file) => file.write(&Self::combined_line("stderr", line)),
+        }
+    }
+
+    fn combined_line(stream: &str, line: &str) -> String {
+        format!(
+            "{} [{}] {}\n",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            stream,
+            line
+        )
+    }
+}
+
+// task names are free-form, so everything that could escape the log directory
+// is replaced
+fn file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            match c {
+                | '/' | '\\' | ':' => '_',
+                | c if c.is_control() => '_',
+                | c => c,
+            }
+        })
+        .collect::<String>();
+    match name.as_str() {
+        | "" | "." | ".." => format!("_{}", name),
+        | _ => name,
+    }
+}
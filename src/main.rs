@@ -1,13 +1,32 @@
-use std::path::PathBuf;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
-use args::{ManualFormat, StdoutFormat};
+use anyhow::{anyhow, Result};
+use args::ManualFormat;
 use multiplexer::Multiplexer;
 
 pub mod args;
+pub mod cache;
+pub mod cargo;
+pub mod changes;
 pub mod config;
+pub mod format;
+pub mod history;
+pub mod import;
+pub mod init;
+pub mod logs;
+pub mod map;
 pub mod multiplexer;
+pub mod parallelism;
+pub mod plan;
 pub mod reference;
+pub mod report;
+pub mod rerun;
+pub mod selection;
+pub mod table;
+pub mod template;
 
 #[deny(unsafe_code)]
 #[tokio::main]
@@ -34,29 +53,156 @@ async fn main() -> Result<()> {
             reference::build_shell_completion(&out_path, &shell)?;
             Ok(())
         },
+        | crate::args::Command::Schema { path } => {
+            reference::build_schema(path.as_deref().map(Path::new))?;
+            Ok(())
+        },
+        | crate::args::Command::List { tasks } => {
+            let rows = [vec!["NAME".to_owned(), "TAGS".to_owned(), "DESCRIPTION".to_owned()]]
+                .into_iter()
+                .chain(tasks.iter().enumerate().map(|(i, v)| {
+                    vec![
+                        v.name.clone().unwrap_or_else(|| i.to_string()),
+                        v.tags.join(", "),
+                        v.description.clone().unwrap_or_default(),
+                    ]
+                }))
+                .collect::<Vec<_>>();
+            for line in table::align(&rows) {
+                println!("{}", line);
+            }
+            Ok(())
+        },
+        | crate::args::Command::Validate { files } => {
+            let mut invalid = 0;
+            for file in &files {
+                match config::Loader::validate(file) {
+                    | Ok(tasks) => println!("{}: ok ({} task(s))", file.display(), tasks.len()),
+                    | Err(e) => {
+                        eprintln!("{}: {}", file.display(), e);
+                        invalid += 1;
+                    },
+                }
+            }
+            if invalid > 0 {
+                return Err(anyhow!("{} of {} config file(s) are invalid", invalid, files.len()));
+            }
+            Ok(())
+        },
+        | crate::args::Command::History { limit } => {
+            ignore_broken_pipe(history::write_list(&mut std::io::stdout(), limit))
+        },
+        | crate::args::Command::Show { run, logs } => {
+            ignore_broken_pipe(history::load(run.as_deref())?.write(&mut std::io::stdout(), logs))
+        },
+        | crate::args::Command::Init { path, format, force } => {
+            init::write(&path, format, force)?;
+            eprintln!("Created {}", path.display());
+            Ok(())
+        },
         | crate::args::Command::Multiplex {
-            program,
             stderr,
             stdout,
             commands,
             parallelism,
+            events,
+            reports,
+            output,
+            logs,
+            cache,
+            max_load,
+            dry_run,
+            history,
         } => {
             let parallelism = parallelism.unwrap_or(commands.len());
-            let result = Multiplexer::new(program, stderr, commands, parallelism).run().await?;
+            if let Some(format) = dry_run {
+                return plan::Plan::new(&commands, parallelism).write(&mut std::io::stdout(), format);
+            }
+            // without a history, tasks are started in the order they are defined in
+            let estimates = history::estimates(&commands).unwrap_or_default();
+            let mut multiplexer = Multiplexer::new(stderr, commands, parallelism)
+                .with_cache(cache)
+                .with_estimates(estimates);
+            if let Some(settings) = logs {
+                multiplexer = multiplexer.with_logs(settings);
+            }
+            if let Some(max_load) = max_load {
+                multiplexer = multiplexer.with_max_load(max_load);
+            }
+            if let Some(path) = events {
+                let writer: Box<dyn Write+Send> = match path.as_str() {
+                    | "-" => Box::new(std::io::stdout()),
+                    | _ => Box::new(std::fs::File::create(path)?),
+                };
+                multiplexer = multiplexer.with_events(writer);
+            }
+            #[cfg(feature = "format+json")]
+            if let Some(args::StdoutFormat::Jsonl) = stdout {
+                multiplexer = multiplexer.with_results(Box::new(std::io::stdout()));
+            }
+            let mut result = multiplexer.run().await?;
+            // only runs stored in the history have an id
+            let run_id = match history {
+                | true => Some(history::id(&result)),
+                | false => None,
+            };
+            result.metadata.run_id = run_id.clone();
+            if let Some(output) = output {
+                format::write_atomic(&output.path, &output.format.serialize_result(&result)?)?;
+            }
+            for report in reports {
+                report.write(&result)?;
+            }
             if let Some(v) = stdout {
                 match v {
                     #[cfg(feature = "format+json")]
-                    | StdoutFormat::Json => {
+                    | args::StdoutFormat::Json => {
                         serde_json::to_writer(std::io::stdout(), &result)?;
                     },
+                    #[cfg(feature = "format+json")]
+                    | args::StdoutFormat::Jsonl => {}, // already streamed while running
                     #[cfg(feature = "format+yaml")]
-                    | StdoutFormat::Yaml => {
+                    | args::StdoutFormat::Yaml => {
                         serde_yml::to_writer(std::io::stdout(), &result)?;
                     },
+                    #[cfg(feature = "format+toml")]
+                    | args::StdoutFormat::Toml => {
+                        std::io::stdout().write_all(result.to_toml()?.as_bytes())?;
+                    },
+                    #[cfg(feature = "format+ron")]
+                    | args::StdoutFormat::Ron => {
+                        std::io::stdout().write_all(format::Format::Ron.serialize_result(&result)?.as_bytes())?;
+                    },
                 }
             }
+            let interrupted = result.metadata.interrupted;
+            // the history must never take down the run
+            if let Some(run_id) = run_id {
+                let stored =
+                    history::Run::new(run_id, std::env::args().collect(), result).and_then(|v| history::store(&v));
+                if let Err(e) = stored {
+                    eprintln!("failed to store the run in the history: {}", e);
+                }
+            }
+            if interrupted {
+                return Err(anyhow!("user interrupt"));
+            }
+            Ok(())
+        },
+    }
+}
+
+// a pipe closed by the reader (like `bobr history | head`) ends the output
+// early
+fn ignore_broken_pipe(result: Result<()>) -> Result<()> {
+    match result {
+        | Err(e)
+            if e.downcast_ref::<std::io::Error>()
+                .is_some_and(|v| v.kind() == std::io::ErrorKind::BrokenPipe) =>
+        {
             Ok(())
         },
+        | v => v,
     }
 }
 
@@ -65,12 +211,15 @@ mod test {
     use anyhow::Result;
     use chrono::Duration;
     use clitest::CliTestSetup;
+    use itertools::Itertools;
 
-    use crate::multiplexer::MultiplexerResult;
+    use crate::multiplexer::{MultiplexerResult, MultiplexerResultTaskStatus};
 
     fn setup_test() -> CliTestSetup {
         let mut setup = CliTestSetup::new();
         setup.with_env("RUST_BACKTRACE", "0");
+        // keeps the runs of the tests out of the history of the developer
+        setup.with_env("XDG_STATE_HOME", "./target/test-state-default");
         setup
     }
 
@@ -135,6 +284,788 @@ mod test {
         let result = setup.run("-e -p4")?;
         assert!(result.status.success()); // must succeed
 
+        // relative to the available CPUs
+        let result = setup.run("-e -c true -p cores+1 --dry-run=json")?;
+        assert!(result.status.success());
+        let plan = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        assert!(plan["parallelism"].as_u64().unwrap() >= 2);
+        let result = setup.run("-e -c true -p 0")?;
+        assert!(!result.status.success());
+        let result = setup.run("-e -c true -p half")?;
+        assert!(!result.status.success());
+
+        // tasks are still started one after the other under load, also when they get
+        // their slots at the same time
+        let result = setup.run("-e -c 'echo a' -c 'echo b' -c 'echo c' -p3 --max-load=0 --stdout=json")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert!(result_typed
+            .tasks
+            .values()
+            .all(|v| v.status == MultiplexerResultTaskStatus::Success));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_config_defaults() -> Result<()> {
+        let setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+
+        // stdout format is taken from the defaults of the config file
+        let result = setup.run("-e -f ./test/defaults.toml")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert_eq!("hello world\n", result_typed.tasks.get(&0).unwrap().stdout);
+        assert_eq!("hello bobr\n", result_typed.tasks.get(&1).unwrap().stdout);
+        assert_eq!("test\n", result_typed.tasks.get(&2).unwrap().stdout);
+
+        // command line flags override the defaults
+        let result = setup.run("-e -f ./test/defaults.toml --stdout=jsonl")?;
+        assert!(result.status.success());
+        assert_eq!(3, std::str::from_utf8(&result.stdout)?.lines().count());
+
+        // an empty program is rejected instead of running nothing
+        let result = setup.run("-f ./test/program.toml")?;
+        assert!(!result.status.success());
+        assert!(result.stderr_str().contains("the program must not be empty"));
+        let result = setup.run("-c 'echo never' --program ''")?;
+        assert!(!result.status.success());
+        assert!(result.stderr_str().contains("the program must not be empty"));
+
+        // experimental settings name the file they are defined in
+        let result = setup.run("-f ./test/defaults.toml")?;
+        assert!(!result.status.success());
+        assert!(result
+            .stderr_str()
+            .contains("experimental setting (defaults.stdout in \"./test/defaults.toml\"), pass -e to enable it"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_config_include() -> Result<()> {
+        let setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+
+        let result = setup.run("-e -f ./test/include.toml --stdout=json")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let tasks = result_typed
+            .tasks
+            .values()
+            .map(|v| (v.name.as_str(), v.stdout.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("root", "root\n"),
+                ("include/backend/build", "include\n"),
+                ("include/frontend/build", "frontend\n"),
+            ],
+            tasks
+        );
+
+        // the same file can not be loaded twice
+        let result = setup.run("-e -f ./test/include.toml -f ./test/include.toml")?;
+        assert!(!result.status.success());
+        assert!(result.stderr_str().contains("duplicate task name \"root\""));
+
+        let result = setup.run("-e -f ./test/cycle.toml")?;
+        assert!(!result.status.success());
+        assert!(result.stderr_str().contains("include cycle detected"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_config_vars() -> Result<()> {
+        let result = setup_test()
+            .with_cargo_flag("--features=\"format+toml\"")
+            .run("-e -f ./test/vars.toml --set name=bobr --stdout=json")?;
+        assert!(result.status.success());
+
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let task = result_typed.tasks.get(&0).unwrap();
+        assert_eq!("greet-bobr", task.name);
+        assert_eq!("echo \"hello bobr\"\n", task.command);
+        assert_eq!("hello bobr\n", task.stdout);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_config_escape() -> Result<()> {
+        let mut setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+        setup.with_env("BOBR_TEST_VALUE", "value");
+        let result = setup.run("-e -f ./test/escape.toml --stdout=json")?;
+        assert!(result.status.success());
+
+        // variables that are not defined in the task env are left to the shell
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let tasks = result_typed
+            .tasks
+            .values()
+            .map(|v| (v.name.as_str(), v.stdout.as_str()))
+            .collect::<Vec<_>>();
+        // expressions of other template languages are kept, like the ones of docker
+        assert_eq!(
+            vec![
+                ("escape", "{{.Names}} value\n"),
+                ("shell", "/\n"),
+                ("docker", "ps --format {{.Names}} {{ json .Labels }}\n")
+            ],
+            tasks
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_config_matrix() -> Result<()> {
+        let result = setup_test()
+            .with_cargo_flag("--features=\"format+toml\"")
+            .run("-e -f ./test/matrix.toml --stdout=json")?;
+        assert!(result.status.success());
+
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let tasks = result_typed
+            .tasks
+            .values()
+            .map(|v| (v.name.as_str(), v.stdout.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("build[target=x86_64,profile=dev]", "x86_64 dev\n"),
+                ("build[target=x86_64,profile=release]", "x86_64 release\n"),
+                ("build[target=aarch64,profile=dev]", "aarch64 dev\n"),
+                ("build[target=aarch64,profile=release]", "aarch64 release\n"),
+            ],
+            tasks
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_config_tags() -> Result<()> {
+        let setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+
+        // dependencies of selected tasks are pulled in and run first
+        let result = setup.run("-e -f ./test/tags.toml --only check --stdout=json")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let names = result_typed.tasks.values().map(|v| v.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["setup", "lint", "test"], names);
+        let setup_task = result_typed.tasks.get(&0).unwrap();
+        let test_task = result_typed.tasks.get(&2).unwrap();
+        assert!(test_task.started.unwrap() >= setup_task.ended.unwrap());
+
+        // dependents of failed tasks are cancelled
+        let result = setup.run("-e -f ./test/tags.toml --only broken --skip lint --stdout=json")?;
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let statuses = result_typed.tasks.values().map(|v| v.status).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                MultiplexerResultTaskStatus::Failed,
+                MultiplexerResultTaskStatus::Cancelled
+            ],
+            statuses
+        );
+
+        let result = setup.run("-e -f ./test/tags.toml --only nope")?;
+        assert!(!result.status.success());
+        assert!(result.stderr_str().contains("no task matches the filter \"nope\""));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_dry_run() -> Result<()> {
+        let setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+
+        let result = setup.run("-f ./test/tags.toml --only check --dry-run=json")?;
+        assert!(result.status.success());
+        let plan = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        let tasks = plan["tasks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| (v["name"].as_str().unwrap(), v["stage"].as_u64().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![("setup", 0), ("lint", 0), ("test", 1)], tasks);
+        assert_eq!("./test/tags.toml", plan["tasks"][0]["source"]);
+        assert_eq!(
+            serde_json::json!(["/bin/sh", "-c", "echo setup"]),
+            plan["tasks"][0]["argv"]
+        );
+
+        // nothing is run
+        let result = setup.run("-c 'touch ./target/dry-run' --dry-run")?;
+        assert!(result.status.success());
+        assert!(!std::path::Path::new("./target/dry-run").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_list_validate() -> Result<()> {
+        let setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+
+        let result = setup.run("list -f ./test/tags.toml")?;
+        assert!(result.status.success());
+        let stdout = std::str::from_utf8(&result.stdout)?;
+        assert!(stdout
+            .lines()
+            .any(|v| v.starts_with("test") && v.ends_with("check, slow")));
+
+        let result = setup.run("validate ./test/tags.toml ./test/include.toml")?;
+        assert!(result.status.success());
+
+        let result = setup.run("validate ./test/tags.toml ./test/cycle.toml")?;
+        assert!(!result.status.success());
+        assert!(result.stderr_str().contains("include cycle detected"));
+
+        // unknown fields do not break running a config, validate reports them
+        let result = setup.run("-f ./test/unknown.toml")?;
+        assert!(result.status.success());
+        let result = setup.run("validate ./test/unknown.toml")?;
+        assert!(!result.status.success());
+        assert!(result.stderr_str().contains("unknown field \"commands[0].nmae\""));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_schema() -> Result<()> {
+        let result = setup_test().run("schema")?;
+        assert!(result.status.success());
+
+        let schema = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        assert_eq!(
+            serde_json::json!(["command"]),
+            schema["definitions"]["Command"]["required"]
+        );
+        assert_eq!(false, schema["definitions"]["Command"]["additionalProperties"]);
+        assert_eq!(schema["properties"]["defaults"], schema["properties"]["settings"]);
+
+        // validate checks the fields of a file against the schema
+        let result = setup_test()
+            .with_cargo_flag("--features=\"format+toml\"")
+            .run("validate ./test/settings.toml")?;
+        assert!(result.status.success(), "{}", result.stderr_str());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_map() -> Result<()> {
+        let setup = setup_test();
+
+        let result = setup.run("map -e --stdout=json -c 'echo {#} {} {.} {/}' --input-from ./test/map.txt")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let tasks = result_typed
+            .tasks
+            .values()
+            .map(|v| (v.name.as_str(), v.command.as_str(), v.stdout.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("a.txt", "echo 1 a.txt a a.txt", "1 a.txt a a.txt\n"),
+                ("b c.txt", "echo 2 'b c.txt' 'b c' 'b c.txt'", "2 b c.txt b c b c.txt\n"),
+                (
+                    "dir/d.tar.gz",
+                    "echo 3 dir/d.tar.gz dir/d.tar d.tar.gz",
+                    "3 dir/d.tar.gz dir/d.tar d.tar.gz\n"
+                ),
+            ],
+            tasks
+        );
+
+        // batches of inputs are appended to templates without placeholders
+        let result = setup.run("map -e --stdout=json -n 2 -c echo --input-from ./test/map.txt")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert_eq!(2, result_typed.tasks.len());
+        assert_eq!("a.txt b c.txt\n", result_typed.tasks.get(&0).unwrap().stdout);
+
+        // repeated inputs get their sequence number appended to stay unique
+        let result = setup.run("map -e --stdout=json -c echo --input-from ./test/map-repeated.txt")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert_eq!(
+            vec!["a", "b", "a (3)"],
+            result_typed.tasks.values().map(|v| v.name.as_str()).collect::<Vec<_>>()
+        );
+        let result = setup.run("map -e --stdout=json -c echo --input-from ./test/map-taken.txt")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert_eq!(
+            vec!["a", "a (2)", "a (2) (3)"],
+            result_typed.tasks.values().map(|v| v.name.as_str()).collect::<Vec<_>>()
+        );
+
+        // NUL-separated records are taken as they are
+        let result = setup.run("map -e --stdout=json -0 -c echo --input-from ./test/map-nul.txt")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert_eq!(
+            vec!["a\r", "b"],
+            result_typed.tasks.values().map(|v| v.name.as_str()).collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_changed_since() -> Result<()> {
+        let setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+
+        let result = setup.run("-e -f ./test/paths.toml --changed-since HEAD --stdout=json")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let always = result_typed.tasks.get(&0).unwrap();
+        assert_eq!(MultiplexerResultTaskStatus::Success, always.status);
+        let untouched = result_typed.tasks.get(&2).unwrap();
+        assert_eq!(MultiplexerResultTaskStatus::Skipped, untouched.status);
+        assert_eq!("", untouched.stdout);
+        let untracked = result_typed.tasks.get(&3).unwrap();
+        assert_eq!(MultiplexerResultTaskStatus::Skipped, untracked.status);
+
+        // files that are not tracked by git yet are changes as well
+        std::fs::create_dir_all("./test/untracked")?;
+        std::fs::write("./test/untracked/new.txt", "new")?;
+        let result = setup.run("-e -f ./test/paths.toml --changed-since HEAD --stdout=json");
+        std::fs::remove_dir_all("./test/untracked")?;
+        let result = result?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let untracked = result_typed.tasks.get(&3).unwrap();
+        assert_eq!(MultiplexerResultTaskStatus::Success, untracked.status);
+
+        let result = setup.run("-f ./test/paths.toml --changed-since does-not-exist")?;
+        assert!(!result.status.success());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_cache() -> Result<()> {
+        let setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+
+        let result = setup.run("-e -f ./test/cache.toml --no-cache --stdout=json")?;
+        assert!(result.status.success());
+        let first = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let first = first.tasks.get(&0).unwrap();
+        assert!(!first.cached);
+        // the cache is kept next to the config file
+        assert!(std::path::Path::new("./test/.bobr/cache").is_dir());
+
+        // the output is replayed and the output files are restored
+        std::fs::remove_file("./target/cache-test/stamp.txt")?;
+        let result = setup.run("-e -f ./test/cache.toml --stdout=json")?;
+        assert!(result.status.success());
+        let second = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let second = second.tasks.get(&0).unwrap();
+        assert!(second.cached);
+        assert_eq!(MultiplexerResultTaskStatus::Success, second.status);
+        assert_eq!(first.stdout, second.stdout);
+        assert_eq!("done\n", second.stderr);
+        assert_eq!(first.stdout, std::fs::read_to_string("./target/cache-test/stamp.txt")?);
+        // restoring is timed like a run
+        assert!(second.duration().is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_logs() -> Result<()> {
+        let _ = std::fs::remove_dir_all("./target/test-logs");
+        let setup = setup_test();
+
+        // names are prefixed with the task id, so they can not collide
+        let logs = "--log-dir=./target/test-logs --log-max-size=2 --log-max-files=2";
+        let result = setup.run(&format!("-e {} -c 'printf \"1\\n2\\n3\\n\"' -c 'echo b'", logs))?;
+        assert!(result.status.success());
+        assert_eq!("3\n", std::fs::read_to_string("./target/test-logs/0-0.stdout.log")?);
+        assert_eq!("2\n", std::fs::read_to_string("./target/test-logs/0-0.stdout.log.1")?);
+        assert_eq!("1\n", std::fs::read_to_string("./target/test-logs/0-0.stdout.log.2")?);
+        assert!(!std::path::Path::new("./target/test-logs/0-0.stdout.log.3").exists());
+        assert_eq!("b\n", std::fs::read_to_string("./target/test-logs/1-1.stdout.log")?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_history() -> Result<()> {
+        let mut setup = setup_test();
+        setup.with_env("XDG_STATE_HOME", "./target/test-state");
+
+        let result = setup.run("-e -c 'echo stored' -c 'echo broken >&2; exit 3' --stdout=json")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let run_id = result_typed.metadata.run_id.unwrap();
+
+        let result = setup.run("history")?;
+        assert!(result.status.success());
+        assert!(String::from_utf8(result.stdout)?.contains(&run_id));
+
+        // the stderr of failed tasks is shown, all output with --logs
+        let result = setup.run(&format!("show {}", run_id))?;
+        assert!(result.status.success());
+        let stdout = String::from_utf8(result.stdout)?;
+        assert!(stdout.contains("failed   3"));
+        assert!(stdout.contains("broken"));
+        assert!(!stdout.contains("(stdout)"));
+        let result = setup.run(&format!("show {} --logs", run_id))?;
+        assert!(String::from_utf8(result.stdout)?.contains("==> 0 (stdout) <==\nstored\n"));
+
+        let result = setup.run("show does-not-exist")?;
+        assert!(!result.status.success());
+
+        // runs are not stored with --no-history and have no id
+        let runs = setup.run("history")?.stdout;
+        let result = setup.run("-e -c 'echo secret' --stdout=json --no-history")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert_eq!(None, result_typed.metadata.run_id);
+        assert_eq!(runs, setup.run("history")?.stdout);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_parallelism() -> Result<()> {
+        let setup = setup_test();
+
+        let result = setup.run("-e -c 'echo a' -c 'echo b' --parallelism=50%")?;
+        assert!(result.status.success());
+
+        // a share of nothing is rejected like 0
+        for value in ["0", "0%"] {
+            let result = setup.run(&format!("-e -c 'echo a' --parallelism={}", value))?;
+            assert!(!result.status.success());
+            assert!(result.stderr_str().contains("parallelism must be greater than 0"));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_longest_first() -> Result<()> {
+        let _ = std::fs::remove_dir_all("./target/test-state-durations");
+        let mut setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+        setup.with_env("XDG_STATE_HOME", "./target/test-state-durations");
+        let order = |result: &MultiplexerResult| {
+            result
+                .tasks
+                .values()
+                .sorted_by_key(|v| v.started)
+                .map(|v| v.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // without a history the tasks start in the order they are defined in, with
+        // one the longest task starts first
+        let result = setup.run("-e -f ./test/durations.toml --parallelism=1 --stdout=json")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert_eq!(vec!["short", "long"], order(&result_typed));
+        let result = setup.run("-e -f ./test/durations.toml --parallelism=1 --stdout=json")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert_eq!(vec!["long", "short"], order(&result_typed));
+
+        // durations are only used for runs started in the same directory
+        let durations = std::fs::read_to_string("./target/test-state-durations/bobr/durations.json")?;
+        let durations = serde_json::from_str::<serde_json::Value>(&durations)?;
+        let cwd = std::env::current_dir()?;
+        assert!(durations[cwd.to_str().unwrap()]["long"].is_object());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_rerun() -> Result<()> {
+        let setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+
+        let result = setup.run("-e -f ./test/tags.toml --only broken --only lint --output=./target/rerun.json")?;
+        assert!(result.status.success());
+
+        // only the failed and cancelled tasks, in the order of their dependencies
+        let result = setup.run("rerun --failed --dry-run=json ./target/rerun.json")?;
+        assert!(result.status.success());
+        let plan = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        let tasks = plan["tasks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| (v["name"].as_str().unwrap(), v["stage"].as_u64().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![("fail", 0), ("after-fail", 1)], tasks);
+
+        let result = setup.run("rerun --dry-run=json ./target/rerun.json")?;
+        let plan = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        assert_eq!(3, plan["tasks"].as_array().unwrap().len());
+
+        let result = setup.run("-e -c 'echo ok' --output=./target/rerun-ok.json")?;
+        assert!(result.status.success());
+        let result = setup.run("rerun --failed ./target/rerun-ok.json")?;
+        assert!(!result.status.success());
+        assert!(result.stderr_str().contains("no failed tasks to run again"));
+
+        // the env is not part of results, it may hold secrets
+        let result = setup.run("-e -f ./test/defaults.toml --output=./target/rerun-env.json")?;
+        assert!(result.status.success());
+        let output = serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string("./target/rerun-env.json")?)?;
+        assert!(output["tasks"]["0"].get("env").is_none());
+        let stdout = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        assert!(stdout["tasks"]["0"].get("env").is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_cargo() -> Result<()> {
+        let setup = setup_test();
+
+        let result = setup.run("cargo --dry-run=json test --all-features -- --nocapture")?;
+        assert!(result.status.success());
+        let plan = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        assert_eq!("bobr", plan["tasks"][0]["name"]);
+        assert_eq!(
+            serde_json::json!(["/bin/sh", "-c", "cargo test -p bobr --all-features -- --nocapture"]),
+            plan["tasks"][0]["argv"]
+        );
+
+        // a "--" directly after the subcommand is passed on as well
+        let result = setup.run("cargo --dry-run=json test -- --nocapture")?;
+        assert!(result.status.success());
+        let plan = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        assert_eq!(
+            serde_json::json!(["/bin/sh", "-c", "cargo test -p bobr -- --nocapture"]),
+            plan["tasks"][0]["argv"]
+        );
+
+        let result = setup.run("--dry-run cargo test")?;
+        assert!(!result.status.success());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_config_format() -> Result<()> {
+        let setup = setup_test().with_cargo_flag("--features=\"format+toml\"");
+
+        // files without extension are detected by their content
+        let result = setup.run("-e -f ./test/tasks --stdout=json")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert_eq!("sniffed\n", result_typed.tasks.get(&0).unwrap().stdout);
+
+        let result = setup.run("-e -f ./test/tasks --format toml")?;
+        assert!(!result.status.success());
+
+        let result = setup.run("-e -f ./test/map.txt")?;
+        assert!(!result.status.success());
+        assert!(result
+            .stderr_str()
+            .contains("enabled features: format+json, format+toml"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_discover() -> Result<()> {
+        let setup = setup_test();
+
+        // the working directory can not be set here, so a task runs bobr from a
+        // subdirectory. The file of the parent is found and its commands run next to
+        // it.
+        let result = setup
+            .run("-e --stdout=json -c 'cd ./test/discover/sub && cargo run -q -- -e --no-history --stdout=json'")?;
+        assert!(result.status.success(), "{}", result.stderr_str());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let inner = serde_json::from_str::<MultiplexerResult>(&result_typed.tasks.get(&0).unwrap().stdout)?;
+        assert!(inner.tasks.get(&0).unwrap().stdout.ends_with("/test/discover\n"));
+
+        // a file of a format that is not enabled is no reason to look further
+        let result = setup.run("-e --stdout=json -c 'cd ./test/discover-unsupported && cargo run -q -- list'")?;
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let inner = result_typed.tasks.get(&0).unwrap();
+        assert_eq!(MultiplexerResultTaskStatus::Failed, inner.status);
+        assert!(inner.stderr.contains("unsupported file format"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_config_import() -> Result<()> {
+        let setup = setup_test();
+
+        let result = setup.run("-e -f ./test/import/Procfile -f ./test/import/Makefile --stdout=json")?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let tasks = result_typed
+            .tasks
+            .values()
+            .map(|v| (v.name.as_str(), v.command.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("web", "echo web"),
+                ("worker", "echo worker"),
+                ("build", "make build"),
+                ("test", "make test"),
+                ("lint", "make lint")
+            ],
+            tasks
+        );
+
+        // only the requested targets, which do not have to be .PHONY
+        let result = setup.run("-f ./test/import/Makefile --make-target out.txt --make-target test --dry-run=json")?;
+        assert!(result.status.success());
+        let plan = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        let names = plan["tasks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["name"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["out.txt", "test"], names);
+        let result = setup.run("-f ./test/import/Makefile --make-target VERSION --dry-run=json")?;
+        assert!(!result.status.success());
+        assert!(result.stderr_str().contains("target \"VERSION\" is not defined"));
+
+        // pre- and post-scripts are run by npm itself
+        let result = setup.run("-f ./test/import/package.json --dry-run=json")?;
+        assert!(result.status.success());
+        let plan = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        assert_eq!(2, plan["tasks"].as_array().unwrap().len());
+        assert_eq!(
+            serde_json::json!(["/bin/sh", "-c", "npm run build"]),
+            plan["tasks"][0]["argv"]
+        );
+        let result = setup.run("-f ./test/import/bun/package.json --dry-run=json")?;
+        let plan = serde_json::from_slice::<serde_json::Value>(&result.stdout)?;
+        assert_eq!(
+            serde_json::json!(["/bin/sh", "-c", "bun run build"]),
+            plan["tasks"][0]["argv"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_events() -> Result<()> {
+        let result = setup_test().run("-e -c 'echo test' --events -")?;
+        assert!(result.status.success());
+
+        let records = std::str::from_utf8(&result.stdout)?
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+        let events = records.iter().map(|v| v["event"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(vec!["status", "stdout", "exit"], events);
+        assert_eq!("test", records[1]["line"]);
+        assert_eq!(0, records[2]["code"]);
+
+        // only one output can be written to stdout
+        let result = setup_test().run("-e -c 'echo test' --events - --stdout=jsonl")?;
+        assert!(!result.status.success());
+        assert!(result
+            .stderr_str()
+            .contains("--stdout and --events - can not be combined, all of them write to stdout"));
+        let result = setup_test().run("-e -c 'echo test' --events - --report=tap")?;
+        assert!(!result.status.success());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_invalid_utf8() -> Result<()> {
+        // the output is read to the end, invalid bytes are replaced
+        let result = setup_test().run(r#"-e -c "printf 'a\377b\n'; printf 'c\377d\n' >&2" --stdout=json"#)?;
+        assert!(result.status.success());
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        let task = result_typed.tasks.get(&0).unwrap();
+        assert_eq!(MultiplexerResultTaskStatus::Success, task.status);
+        assert_eq!("a\u{FFFD}b\n", task.stdout);
+        assert_eq!("c\u{FFFD}d\n", task.stderr);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_report_junit_tap() -> Result<()> {
+        let result = setup_test().run("-e -c 'echo test' -c 'exit 3' --report=tap")?;
+        assert!(result.status.success());
+        assert_eq!(
+            vec!["TAP version 13", "1..2", "ok 1 - 0", "not ok 2 - 1"],
+            std::str::from_utf8(&result.stdout)?.lines().take(4).collect::<Vec<_>>()
+        );
+
+        let result = setup_test().run("-e -c 'echo test' -c 'exit 3' --report=junit")?;
+        assert!(result.status.success());
+        let stdout = std::str::from_utf8(&result.stdout)?;
+        assert!(stdout.contains(r#"<testsuites name="bobr" tests="2" failures="1""#));
+        assert!(stdout.contains("<system-out>test\n</system-out>"));
+        assert!(stdout.contains(r#"<failure message="exited with code 3""#));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_stdout_jsonl() -> Result<()> {
+        let result = setup_test().run("-e -c 'sleep 1 && echo slow' -c 'echo fast' --stdout=jsonl")?;
+        assert!(result.status.success());
+
+        // results are streamed in order of completion
+        let records = std::str::from_utf8(&result.stdout)?
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(2, records.len());
+        assert_eq!(1, records[0]["id"]);
+        assert_eq!("fast\n", records[0]["stdout"]);
+        assert_eq!(0, records[1]["id"]);
+        assert_eq!("slow\n", records[1]["stdout"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_output_file() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("bobr-test-output-{}.json", std::process::id()));
+        let result = setup_test().run(&format!("-e -c 'echo test' --output={}", path.display()))?;
+        assert!(result.status.success());
+        assert!(result.stdout.is_empty());
+
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&std::fs::read(&path)?)?;
+        std::fs::remove_file(&path)?;
+        assert!(!result_typed.metadata.interrupted);
+        assert_eq!("test\n", result_typed.tasks.get(&0).unwrap().stdout);
+
+        // unknown formats are rejected before anything runs
+        let result = setup_test().run("-e -c 'echo test' --output=result.unknown")?;
+        assert!(!result.status.success());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_feature_format_toml() -> Result<()> {
+        // run without feature
+        let result = setup_test().run("-e --stdout=toml")?;
+        assert!(!result.status.success()); // can not succeed
+
+        // run with format+toml feature
+        let result = setup_test()
+            .with_cargo_flag("--features=\"format+toml\"")
+            .run("-e -c 'echo test' --stdout=toml")?;
+        assert!(result.status.success()); // must succeed
+        let stdout = std::str::from_utf8(&result.stdout)?;
+        assert!(stdout.contains("[tasks.0]\n"));
+        assert!(stdout.contains("command = \"echo test\"\n"));
+
         Ok(())
     }
 
@@ -152,4 +1083,38 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    pub async fn test_feature_format_json5() -> Result<()> {
+        // run without feature
+        let result = setup_test().run("-e -f ./test/example.json5")?;
+        assert!(!result.status.success()); // can not succeed
+
+        // run with format+json5 feature
+        let result = setup_test()
+            .with_cargo_flag("--features=\"format+json5\"")
+            .run("-e -f ./test/example.json5 --stdout=json")?;
+        assert!(result.status.success()); // must succeed
+        let result_typed = serde_json::from_slice::<MultiplexerResult>(&result.stdout)?;
+        assert_eq!("world\n", result_typed.tasks.get(&1).unwrap().stdout);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_feature_format_ron() -> Result<()> {
+        // run without feature
+        let result = setup_test().run("-e --stdout=ron")?;
+        assert!(!result.status.success()); // can not succeed
+
+        // run with format+ron feature
+        let result = setup_test()
+            .with_cargo_flag("--features=\"format+ron\"")
+            .run("-e -f ./test/example.ron --stdout=ron")?;
+        assert!(result.status.success()); // must succeed
+        let stdout = std::str::from_utf8(&result.stdout)?;
+        assert!(stdout.contains("stdout: \"hello\\n\""));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,119 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+};
+
+use crate::multiplexer::TaskDefinition;
+
+// splits the input into records, empty records are ignored. Lines may end with
+// \r\n, NUL-separated records are taken as they are.
+pub fn records(content: &str, separator: char) -> Vec<String> {
+    content
+        .split(separator)
+        .map(|v| {
+            match separator {
+                | '\n' => v.strip_suffix('\r').unwrap_or(v),
+                | _ => v,
+            }
+        })
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_owned())
+        .collect()
+}
+
+// instantiates the template once per batch of inputs, the tasks are named after
+// their inputs. Repeated inputs get their sequence number appended, as task
+// names have to be unique.
+pub fn tasks(template: &str, inputs: &[String], batch: usize, program: &[String]) -> Vec<TaskDefinition> {
+    let mut names = HashSet::new();
+    inputs
+        .chunks(batch.max(1))
+        .enumerate()
+        .map(|(i, inputs)| {
+            let base = inputs.join(" ");
+            let mut name = base.clone();
+            // the name with the sequence number can be taken by an input as well
+            let mut sequence = i + 1;
+            while !names.insert(name.clone()) {
+                name = format!("{} ({})", base, sequence);
+                sequence += 1;
+            }
+            TaskDefinition {
+                name: Some(name),
+                description: None,
+                program: program.to_vec(),
+                command: instantiate(template, inputs, i + 1),
+                cwd: None,
+                env: BTreeMap::new(),
+                log: None,
+                tags: Vec::new(),
+                depends_on: Vec::new(),
+                order_only: false,
+                source: None,
+                paths: Vec::new(),
+                skip: None,
+                cache: None,
+            }
+        })
+        .collect()
+}
+
+// replaces the placeholders like GNU parallel does:
+// {} the input, {.} without extension, {/} basename, {#} sequence number.
+// Inputs are quoted for the shell, a template without placeholders gets the
+// inputs appended.
+fn instantiate(template: &str, inputs: &[String], sequence: usize) -> String {
+    let expand = |f: fn(&str) -> String| inputs.iter().map(|v| quote(&f(v))).collect::<Vec<_>>().join(" ");
+
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    let mut replaced = false;
+    while let Some(pos) = rest.find('{') {
+        output.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let (value, length) = if rest.starts_with("{}") {
+            (expand(|v| v.to_owned()), 2)
+        } else if rest.starts_with("{.}") {
+            (
+                expand(|v| Path::new(v).with_extension("").to_string_lossy().into_owned()),
+                3,
+            )
+        } else if rest.starts_with("{/}") {
+            (
+                expand(|v| {
+                    Path::new(v)
+                        .file_name()
+                        .map(|v| v.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| v.to_owned())
+                }),
+                3,
+            )
+        } else if rest.starts_with("{#}") {
+            (sequence.to_string(), 3)
+        } else {
+            ("{".to_owned(), 1)
+        };
+        replaced |= length > 1;
+        output.push_str(&value);
+        rest = &rest[length..];
+    }
+    output.push_str(rest);
+
+    if !replaced {
+        output.push(' ');
+        output.push_str(&expand(|v| v.to_owned()));
+    }
+    output
+}
+
+// quotes a value for the shell if it contains anything but safe characters
+pub fn quote(value: &str) -> String {
+    let safe = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./=:,+@%".contains(c));
+    match safe {
+        | true => value.to_owned(),
+        | false => format!("'{}'", value.replace('\'', r#"'\''"#)),
+    }
+}
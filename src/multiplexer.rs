@@ -1,29 +1,39 @@
 use std::{
-    collections::{BTreeMap, VecDeque},
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BinaryHeap, VecDeque},
     io::{stderr, BufWriter, Write},
-    sync::Arc,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc,
+    },
 };
 
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use crossterm::{
     cursor::MoveTo,
     style::{Print, Stylize},
     terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use flume::Receiver;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use signal_hook::{
     consts::{SIGINT, SIGTERM},
     iterator::Signals,
 };
 use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    io::{AsyncBufReadExt, BufReader},
     process::Command,
-    sync::Semaphore,
+    sync::{oneshot, watch},
     task::JoinSet,
 };
 
+use crate::{
+    cache::{CacheSettings, CacheSpec},
+    logs::{LogMode, LogSettings, TaskLogger},
+};
+
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct MultiplexerResult {
@@ -31,23 +41,118 @@ pub struct MultiplexerResult {
     pub tasks: BTreeMap<usize, MultiplexerResultDataTask>,
 }
 
+impl MultiplexerResult {
+    #[cfg(feature = "format+toml")]
+    pub fn to_toml(&self) -> Result<String> {
+        // TOML tables only support string keys
+        #[derive(serde::Serialize)]
+        struct TomlResult<'a> {
+            metadata: &'a MultiplexerResultMetadata,
+            #[serde(serialize_with = "serialize_string_keys")]
+            tasks: &'a BTreeMap<usize, MultiplexerResultDataTask>,
+        }
+        fn serialize_string_keys<S: serde::Serializer>(
+            tasks: &&BTreeMap<usize, MultiplexerResultDataTask>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(tasks.iter().map(|(k, v)| (k.to_string(), v)))
+        }
+
+        Ok(toml::to_string(&TomlResult {
+            metadata: &self.metadata,
+            tasks: &self.tasks,
+        })?)
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct MultiplexerResultMetadata {
     pub started: DateTime<Utc>,
     pub ended: DateTime<Utc>,
+    #[serde(default)]
+    pub interrupted: bool,
+    // the id of the run in the history
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct MultiplexerResultDataTask {
+    pub name: String,
+    pub command: String,
+    pub status: MultiplexerResultTaskStatus,
+    pub exit_code: Option<i32>,
+    pub started: Option<DateTime<Utc>>,
+    pub ended: Option<DateTime<Utc>>,
     pub stdout: String,
+    pub stderr: String,
+    // the output was replayed from the cache instead of running the task
+    #[serde(default)]
+    pub cached: bool,
+    // the resolved task, so it can be run again. The cwd is absolute, the env is
+    // only kept in the history as it may hold secrets.
+    #[serde(default)]
+    pub program: Vec<String>,
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    #[serde(skip)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+impl MultiplexerResultDataTask {
+    pub fn duration(&self) -> Option<chrono::Duration> {
+        Some(self.ended? - self.started?)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiplexerResultTaskStatus {
+    Pending,
+    Running,
+    Success,
+    Failed,
+    // not run because a dependency did not succeed
+    Cancelled,
+    // not run because there was nothing to do, like for unchanged tasks
+    Skipped,
+}
+
+#[derive(Debug)]
+pub struct TaskDefinition {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub program: Vec<String>,
+    pub command: String,
+    pub cwd: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+    pub log: Option<LogMode>,
+    pub tags: Vec<String>,
+    // names of the tasks that have to succeed before this one starts
+    pub depends_on: Vec<String>,
+    // the dependencies only order the tasks, this one runs even if they failed
+    pub order_only: bool,
+    // the config file the task was loaded from
+    pub source: Option<PathBuf>,
+    // glob patterns of the files the task depends on
+    pub paths: Vec<PathBuf>,
+    // the reason the task is not run, like being unchanged
+    pub skip: Option<String>,
+    // tasks with a cache spec are not run again if their inputs did not change
+    pub cache: Option<CacheSpec>,
 }
 
 #[derive(Debug, Eq, PartialEq)]
 enum TaskStatusCompleted {
     Success,
     Failed(Option<i32>),
+    Cancelled,
+    Skipped(String),
+    Cached,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -64,58 +169,323 @@ enum TaskEvent {
 }
 
 struct Task {
+    name: String,
+    program: Vec<String>,
     command: String,
+    cwd: Option<PathBuf>,
+    env: BTreeMap<String, String>,
+    log: Option<LogMode>,
+    depends_on: Vec<usize>,
+    depends_on_names: Vec<String>,
+    order_only: bool,
+    // how long the task is expected to take, from previous runs
+    estimate: Option<Duration>,
+    skip: Option<String>,
+    cache: Option<CacheSpec>,
     status: TaskStatus,
+    started: Option<DateTime<Utc>>,
+    ended: Option<DateTime<Utc>>,
     stderr: VecDeque<String>,
+    stderr_full: String,
     stdout: String,
 }
 
+impl Task {
+    fn to_result(&self) -> MultiplexerResultDataTask {
+        let (status, exit_code) = match &self.status {
+            | TaskStatus::Pending => (MultiplexerResultTaskStatus::Pending, None),
+            | TaskStatus::Running => (MultiplexerResultTaskStatus::Running, None),
+            | TaskStatus::Completed(TaskStatusCompleted::Success) => (MultiplexerResultTaskStatus::Success, Some(0)),
+            | TaskStatus::Completed(TaskStatusCompleted::Failed(code)) => (MultiplexerResultTaskStatus::Failed, *code),
+            | TaskStatus::Completed(TaskStatusCompleted::Cancelled) => (MultiplexerResultTaskStatus::Cancelled, None),
+            | TaskStatus::Completed(TaskStatusCompleted::Skipped(_)) => (MultiplexerResultTaskStatus::Skipped, None),
+            | TaskStatus::Completed(TaskStatusCompleted::Cached) => (MultiplexerResultTaskStatus::Success, Some(0)),
+        };
+        MultiplexerResultDataTask {
+            name: self.name.clone(),
+            command: self.command.clone(),
+            status,
+            exit_code,
+            started: self.started,
+            ended: self.ended,
+            stdout: self.stdout.clone(),
+            stderr: self.stderr_full.clone(),
+            cached: self.status == TaskStatus::Completed(TaskStatusCompleted::Cached),
+            program: self.program.clone(),
+            cwd: match &self.cwd {
+                | Some(v) => std::path::absolute(v).ok(),
+                | None => std::env::current_dir().ok(),
+            },
+            env: self.env.clone(),
+            depends_on: self.depends_on_names.clone(),
+        }
+    }
+}
+
+// tasks waiting for a slot of the run, the longest are started first
+type Priority = (bool, i64, Reverse<usize>);
+
+struct Waiter {
+    priority: Priority,
+    tx: oneshot::Sender<Slot>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority)
+    }
+}
+
+struct BudgetState {
+    free: usize,
+    // nothing is handed out before all initially ready tasks are queued
+    held: bool,
+    waiting: BinaryHeap<Waiter>,
+}
+
+// hands out the slots of the run to the waiting tasks by priority (longest
+// processing time first)
+struct Budget {
+    state: Mutex<BudgetState>,
+}
+
+// a slot is given back to the budget when it is dropped
+struct Slot {
+    budget: Arc<Budget>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.budget.state.lock().free += 1;
+        self.budget.dispatch();
+    }
+}
+
+impl Budget {
+    fn new(slots: usize) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(BudgetState {
+                free: slots,
+                held: true,
+                waiting: BinaryHeap::new(),
+            }),
+        })
+    }
+
+    // tasks with an estimate are ordered by it, tasks without one go first in the
+    // order they were defined in
+    fn priority(id: usize, estimate: Option<Duration>) -> Priority {
+        (
+            estimate.is_none(),
+            estimate.map(|v| v.num_milliseconds()).unwrap_or(0),
+            Reverse(id),
+        )
+    }
+
+    fn request(self: &Arc<Self>, priority: Priority) -> oneshot::Receiver<Slot> {
+        let (tx, rx) = oneshot::channel();
+        self.state.lock().waiting.push(Waiter { priority, tx });
+        self.dispatch();
+        rx
+    }
+
+    fn open(self: &Arc<Self>) {
+        self.state.lock().held = false;
+        self.dispatch();
+    }
+
+    fn dispatch(self: &Arc<Self>) {
+        let mut granted = Vec::new();
+        {
+            let mut state = self.state.lock();
+            while !state.held && state.free > 0 {
+                match state.waiting.pop() {
+                    | Some(waiter) => {
+                        state.free -= 1;
+                        granted.push(waiter.tx);
+                    },
+                    | None => break,
+                }
+            }
+        }
+        // a slot sent to a task that is gone is dropped and thereby given back
+        for tx in granted {
+            let _ = tx.send(Slot { budget: self.clone() });
+        }
+    }
+}
+
 pub struct Multiplexer {
-    program: Vec<String>,
     stderr: usize,
     tasks: BTreeMap<usize, RwLock<Task>>,
     parallelism: usize,
+    events: Option<Box<dyn Write+Send>>,
+    results: Option<Box<dyn Write+Send>>,
+    logs: Option<LogSettings>,
+    cache: Option<Arc<CacheSettings>>,
+    max_load: Option<f64>,
+}
+
+// the 1-minute load average, only available on Linux
+pub fn load_average() -> Option<f64> {
+    std::fs::read_to_string("/proc/loadavg")
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse::<f64>()
+        .ok()
+}
+
+// the cache hashes and copies files, which must not block the runtime
+async fn blocking<T: Send+'static>(f: impl FnOnce() -> Result<T>+Send+'static) -> Result<T> {
+    tokio::task::spawn_blocking(f).await?
 }
 
 impl Multiplexer {
-    pub fn new(program: Vec<String>, stderr: usize, tasks: Vec<String>, processes: usize) -> Self {
+    pub fn new(stderr: usize, tasks: Vec<TaskDefinition>, processes: usize) -> Self {
+        let ids = tasks
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.name.clone().unwrap_or_else(|| i.to_string()), i))
+            .collect::<BTreeMap<_, _>>();
         let mut task_map = BTreeMap::<usize, RwLock<Task>>::new();
-        for i in 0..tasks.len() {
+        for (i, task) in tasks.into_iter().enumerate() {
             task_map.insert(
                 i,
                 RwLock::new(Task {
-                    command: tasks[i].clone(),
+                    name: task.name.unwrap_or_else(|| i.to_string()),
+                    program: task.program,
+                    command: task.command,
+                    cwd: task.cwd,
+                    env: task.env,
+                    log: task.log,
+                    depends_on: task.depends_on.iter().filter_map(|v| ids.get(v).copied()).collect(),
+                    depends_on_names: task.depends_on,
+                    order_only: task.order_only,
+                    estimate: None,
+                    skip: task.skip,
+                    cache: task.cache,
                     status: TaskStatus::Pending,
+                    started: None,
+                    ended: None,
                     stderr: VecDeque::<_>::new(),
+                    stderr_full: String::new(),
                     stdout: String::new(),
                 }),
             );
         }
 
         Self {
-            program,
             stderr,
             tasks: task_map,
             parallelism: processes,
+            events: None,
+            results: None,
+            logs: None,
+            cache: None,
+            max_load: None,
         }
     }
 
+    // writes every task event as a newline-delimited JSON record
+    pub fn with_events(mut self, writer: Box<dyn Write+Send>) -> Self {
+        self.events = Some(writer);
+        self
+    }
+
+    // writes the result of every task as a newline-delimited JSON record as soon as
+    // it completes
+    pub fn with_results(mut self, writer: Box<dyn Write+Send>) -> Self {
+        self.results = Some(writer);
+        self
+    }
+
+    // tees the stdout and stderr of every task into files in the given directory
+    pub fn with_logs(mut self, settings: LogSettings) -> Self {
+        self.logs = Some(settings);
+        self
+    }
+
+    // holds off starting tasks while the load average is above the given value
+    pub fn with_max_load(mut self, max_load: f64) -> Self {
+        self.max_load = Some(max_load);
+        self
+    }
+
+    // the expected duration of tasks by id, the longest tasks are started first
+    pub fn with_estimates(self, estimates: BTreeMap<usize, Duration>) -> Self {
+        for (id, estimate) in estimates {
+            if let Some(task) = self.tasks.get(&id) {
+                task.write().estimate = Some(estimate);
+            }
+        }
+        self
+    }
+
+    // skips the tasks with a cache spec whose successful result is cached
+    pub fn with_cache(mut self, settings: CacheSettings) -> Self {
+        self.cache = Some(Arc::new(settings));
+        self
+    }
+
     pub async fn run(self) -> Result<MultiplexerResult> {
+        let mut loggers = BTreeMap::<usize, TaskLogger>::new();
+        if let Some(settings) = &self.logs {
+            std::fs::create_dir_all(&settings.dir)?;
+            for (id, task) in self.tasks.iter() {
+                let task = task.read();
+                if let Some(logger) = TaskLogger::create(settings, *id, &task.name, task.log.unwrap_or(LogMode::Split))?
+                {
+                    loggers.insert(*id, logger);
+                }
+            }
+        }
+
         let time_start = Utc::now();
         let (task_event_tx, task_event_rx) = flume::unbounded::<TaskEvent>();
 
         let mut joins = JoinSet::new();
-        let budget = Arc::new(Semaphore::new(self.parallelism));
+        let budget = Budget::new(self.parallelism);
+        let running = Arc::new(AtomicUsize::new(0));
+        // every task publishes whether it succeeded to the tasks depending on it
+        let (done_tx, done_rx): (BTreeMap<_, _>, BTreeMap<_, _>) = self
+            .tasks
+            .keys()
+            .map(|id| {
+                let (tx, rx) = watch::channel(None::<bool>);
+                ((*id, tx), (*id, rx))
+            })
+            .unzip();
+        let mut done_tx = done_tx;
         for command in self.tasks.iter() {
             let report_channel = task_event_tx.clone();
+            let task = command.1.read();
             // first item is shell to execute commands in (like "/bin/sh")
-            let mut cmd_proc = Command::new(&self.program[0]);
+            let mut cmd_proc = Command::new(&task.program[0]);
             // remaining items are arguments to shell (like "-c")
-            for arg in &self.program[1..] {
+            for arg in &task.program[1..] {
                 cmd_proc.arg(arg);
             }
             // final argument is the command itself
-            cmd_proc.arg(&command.1.read().command);
+            cmd_proc.arg(&task.command);
+            if let Some(cwd) = &task.cwd {
+                cmd_proc.current_dir(cwd);
+            }
+            cmd_proc.envs(&task.env);
 
             cmd_proc.stdin(std::process::Stdio::null());
             cmd_proc.stdout(std::process::Stdio::piped());
@@ -124,34 +494,199 @@ impl Multiplexer {
             // spawn child process as member of JoinSet
             let task_id = command.0.clone();
             let task_budget = budget.clone();
+            let task_running = running.clone();
+            let max_load = self.max_load;
+            // tasks that are ready right away queue up before any of them starts
+            let priority = Budget::priority(task_id, task.estimate);
+            let queued = task.depends_on.is_empty().then(|| budget.request(priority));
+            let done = done_tx.remove(&task_id).unwrap();
+            let dependencies = task.depends_on.iter().map(|v| done_rx[v].clone()).collect::<Vec<_>>();
+            let order_only = task.order_only;
+            let skip = task.skip.clone();
+            let cache = self.cache.clone().zip(task.cache.clone());
+            let argv = task.program.iter().chain([&task.command]).cloned().collect::<Vec<_>>();
+            let (cwd, env) = (task.cwd.clone(), task.env.clone());
             joins.spawn(async move {
-                let _seq_lock = task_budget.acquire().await;
-                let mut child_proc = cmd_proc.spawn().unwrap();
+                // skipped tasks count as succeeded for the tasks depending on them
+                if let Some(reason) = skip {
+                    let _ = report_channel.send(TaskEvent::Update {
+                        id: task_id,
+                        status: TaskStatus::Completed(TaskStatusCompleted::Skipped(reason)),
+                    });
+                    done.send_replace(Some(true));
+                    return;
+                }
+                // dependencies are awaited before taking a slot, so waiting tasks never
+                // block others
+                for mut dependency in dependencies {
+                    let success = dependency
+                        .wait_for(|v| v.is_some())
+                        .await
+                        .is_ok_and(|v| *v == Some(true));
+                    if !success && !order_only {
+                        let _ = report_channel.send(TaskEvent::Update {
+                            id: task_id,
+                            status: TaskStatus::Completed(TaskStatusCompleted::Cancelled),
+                        });
+                        done.send_replace(Some(false));
+                        return;
+                    }
+                }
+                // inputs are hashed once the dependencies are done, they may produce them
+                let key = match cache.clone() {
+                    | Some((settings, spec)) => {
+                        match blocking(move || settings.key(&spec, &argv, cwd.as_deref(), &env)).await {
+                            | Ok(v) => Some(v),
+                            | Err(err) => {
+                                let _ = report_channel.send(TaskEvent::Stderr {
+                                    id: task_id,
+                                    line: format!("failed to hash the inputs, not caching: {}", err),
+                                });
+                                None
+                            },
+                        }
+                    },
+                    | None => None,
+                };
+                if let (Some((settings, _)), Some(key)) = (&cache, &key) {
+                    if !settings.refresh {
+                        let lookup = {
+                            let (settings, key) = (settings.clone(), key.clone());
+                            blocking(move || settings.lookup(&key))
+                        };
+                        if let Ok(Some(record)) = lookup.await {
+                            // restoring the outputs is what a hit takes, so it is timed like a run
+                            let _ = report_channel.send(TaskEvent::Update {
+                                id: task_id,
+                                status: TaskStatus::Running,
+                            });
+                            let restored = {
+                                let (settings, key) = (settings.clone(), key.clone());
+                                blocking(move || settings.restore(&key, &record).map(|_| record))
+                            };
+                            if let Ok(record) = restored.await {
+                                if !record.stdout.is_empty() {
+                                    let _ = report_channel.send(TaskEvent::Stdout {
+                                        id: task_id,
+                                        content: record.stdout,
+                                    });
+                                }
+                                for line in record.stderr.lines() {
+                                    let _ = report_channel.send(TaskEvent::Stderr {
+                                        id: task_id,
+                                        line: line.to_owned(),
+                                    });
+                                }
+                                let _ = report_channel.send(TaskEvent::Update {
+                                    id: task_id,
+                                    status: TaskStatus::Completed(TaskStatusCompleted::Cached),
+                                });
+                                done.send_replace(Some(true));
+                                return;
+                            }
+                        }
+                    }
+                }
+                let overloaded = || max_load.is_some_and(|max| load_average().is_some_and(|v| v > max));
+                let mut queued = queued;
+                let _slot = loop {
+                    let slot = match queued.take() {
+                        | Some(v) => v,
+                        | None => task_budget.request(priority),
+                    }
+                    .await;
+                    // like make -l, a task is started regardless if nothing else is running.
+                    // The running count is reserved right away, so tasks getting a slot at
+                    // the same time can not all pass the check.
+                    let busy = overloaded();
+                    if task_running
+                        .fetch_update(AtomicOrdering::SeqCst, AtomicOrdering::SeqCst, |n| {
+                            (n == 0 || !busy).then_some(n + 1)
+                        })
+                        .is_ok()
+                    {
+                        break slot;
+                    }
+                    // the slot is given back while waiting for the load to drop
+                    drop(slot);
+                    while task_running.load(AtomicOrdering::SeqCst) > 0 && overloaded() {
+                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    }
+                };
+                let mut child_proc = match cmd_proc.spawn() {
+                    | Ok(v) => v,
+                    | Err(err) => {
+                        task_running.fetch_sub(1, AtomicOrdering::SeqCst);
+                        // surface the error (like a missing cwd) instead of leaving the task pending
+                        let _ = report_channel.send(TaskEvent::Stderr {
+                            id: task_id,
+                            line: format!("failed to spawn process: {}", err),
+                        });
+                        let _ = report_channel.send(TaskEvent::Update {
+                            id: task_id,
+                            status: TaskStatus::Completed(TaskStatusCompleted::Failed(None)),
+                        });
+                        done.send_replace(Some(false));
+                        return;
+                    },
+                };
                 // ignore error
                 let _ = report_channel.send(TaskEvent::Update {
                     id: task_id.clone(),
                     status: TaskStatus::Running,
                 });
 
+                // drain both pipes concurrently to report stdout lines as they arrive, the
+                // output is only kept here if it is cached
+                let capture = key.is_some();
+                // output that is no valid UTF-8 is converted lossily, the pipes are drained
+                // until EOF so the process never blocks on a full pipe
                 let stderr = child_proc.stderr.take().unwrap();
-                let mut stderr_reader = BufReader::new(stderr).lines();
-                while let Ok(Some(line)) = stderr_reader.next_line().await {
-                    let _ = report_channel.send(TaskEvent::Stderr {
-                        id: task_id.clone(),
-                        line,
-                    });
-                }
-
+                let stderr_fut = async {
+                    let mut captured = String::new();
+                    let mut stderr_reader = BufReader::new(stderr);
+                    let mut buffer = Vec::new();
+                    while let Ok(1..) = stderr_reader.read_until(b'\n', &mut buffer).await {
+                        let content = String::from_utf8_lossy(&buffer);
+                        let line = content.strip_suffix('\n').unwrap_or(&content);
+                        let line = line.strip_suffix('\r').unwrap_or(line).to_owned();
+                        buffer.clear();
+                        if capture {
+                            captured.push_str(&line);
+                            captured.push('\n');
+                        }
+                        let _ = report_channel.send(TaskEvent::Stderr { id: task_id, line });
+                    }
+                    captured
+                };
                 let stdout = child_proc.stdout.take().unwrap();
-                let mut stdout_out = String::new();
-                let mut stdout_reader = BufReader::new(stdout);
-                stdout_reader.read_to_string(&mut stdout_out).await.unwrap();
-                let _ = report_channel.send(TaskEvent::Stdout {
-                    id: task_id.clone(),
-                    content: stdout_out,
-                });
+                let stdout_fut = async {
+                    let mut captured = String::new();
+                    let mut stdout_reader = BufReader::new(stdout);
+                    let mut buffer = Vec::new();
+                    while let Ok(1..) = stdout_reader.read_until(b'\n', &mut buffer).await {
+                        let content = String::from_utf8_lossy(&buffer).into_owned();
+                        buffer.clear();
+                        if capture {
+                            captured.push_str(&content);
+                        }
+                        let _ = report_channel.send(TaskEvent::Stdout { id: task_id, content });
+                    }
+                    captured
+                };
+                let (stderr, stdout) = tokio::join!(stderr_fut, stdout_fut);
 
                 let exit_code = child_proc.wait().await.unwrap();
+                task_running.fetch_sub(1, AtomicOrdering::SeqCst);
+                if let (Some((settings, spec)), Some(key), true) = (cache, key, exit_code.success()) {
+                    if let Err(err) = blocking(move || settings.store(&key, &spec, &stdout, &stderr)).await {
+                        let _ = report_channel.send(TaskEvent::Stderr {
+                            id: task_id,
+                            line: format!("failed to cache the result: {}", err),
+                        });
+                    }
+                }
+                done.send_replace(Some(exit_code.success()));
                 let status = if exit_code.success() {
                     TaskStatusCompleted::Success
                 } else {
@@ -165,12 +700,15 @@ impl Multiplexer {
             });
         }
         drop(task_event_tx);
+        budget.open();
 
         let mut signals = Signals::new([SIGINT, SIGTERM]).unwrap();
         let signals_handle = signals.handle();
 
         // task handling abort signals
-        let abort_fut = tokio::spawn(async move { signals.wait() });
+        let abort_fut = tokio::task::spawn_blocking(move || {
+            let _ = signals.wait();
+        });
         // task handling command execution
         let command_fut = tokio::spawn(async move { while let Some(_) = joins.join_next().await {} });
 
@@ -178,15 +716,22 @@ impl Multiplexer {
             rx: task_event_rx,
             stderr: self.stderr,
             tasks: &self.tasks,
+            events: self.events,
+            results: self.results,
+            loggers,
+            parallelism: self.parallelism,
+            started: time_start,
         };
 
-        tokio::select! {
+        // on interrupt, the partial result is returned and marked as such
+        let interrupted = tokio::select! {
             _ = abort_fut => {
-                return Err(anyhow::anyhow!("user interrupt"));
+                crossterm::execute!(std::io::stderr(), LeaveAlternateScreen).unwrap();
+                true
             }, // abort signal was received
-            _ = command_fut => {}, // all tasks were executed
-            _ = event_handler.run() => {}, // reporting task failed
-        }
+            // all tasks were executed and their events were processed
+            _ = async { tokio::join!(command_fut, event_handler.run()) } => false,
+        };
         signals_handle.close();
         let time_end = Utc::now();
 
@@ -194,48 +739,164 @@ impl Multiplexer {
             metadata: MultiplexerResultMetadata {
                 started: time_start,
                 ended: time_end,
+                interrupted,
+                run_id: None,
             },
             tasks: BTreeMap::<_, _>::new(),
         };
-        for t in self.tasks.into_iter() {
-            let task = t.1.into_inner();
-            data.tasks
-                .insert(t.0.clone(), MultiplexerResultDataTask { stdout: task.stdout });
+        for t in self.tasks.iter() {
+            data.tasks.insert(t.0.clone(), t.1.read().to_result());
         }
 
         Ok(data)
     }
 }
 
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+struct EventRecord<'a> {
+    timestamp: DateTime<Utc>,
+    id: usize,
+    name: &'a str,
+    #[serde(flatten)]
+    data: EventRecordData<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum EventRecordData<'a> {
+    Status { status: &'static str },
+    Exit { success: bool, code: Option<i32> },
+    Stderr { line: &'a str },
+    Stdout { line: &'a str },
+}
+
+impl<'a> EventRecordData<'a> {
+    fn from_event(event: &'a TaskEvent) -> Self {
+        match event {
+            | TaskEvent::Update { status, .. } => {
+                match status {
+                    | TaskStatus::Pending => Self::Status { status: "pending" },
+                    | TaskStatus::Running => Self::Status { status: "running" },
+                    | TaskStatus::Completed(TaskStatusCompleted::Success) => {
+                        Self::Exit {
+                            success: true,
+                            code: Some(0),
+                        }
+                    },
+                    | TaskStatus::Completed(TaskStatusCompleted::Failed(code)) => {
+                        Self::Exit {
+                            success: false,
+                            code: *code,
+                        }
+                    },
+                    | TaskStatus::Completed(TaskStatusCompleted::Cancelled) => Self::Status { status: "cancelled" },
+                    | TaskStatus::Completed(TaskStatusCompleted::Skipped(_)) => Self::Status { status: "skipped" },
+                    | TaskStatus::Completed(TaskStatusCompleted::Cached) => Self::Status { status: "cached" },
+                }
+            },
+            | TaskEvent::Stderr { line, .. } => Self::Stderr { line },
+            | TaskEvent::Stdout { content, .. } => {
+                Self::Stdout {
+                    line: content.strip_suffix('\n').unwrap_or(content),
+                }
+            },
+        }
+    }
+}
+
 struct TaskEventReporter<'a> {
     rx: Receiver<TaskEvent>,
     stderr: usize,
     tasks: &'a BTreeMap<usize, RwLock<Task>>,
+    events: Option<Box<dyn Write+Send>>,
+    results: Option<Box<dyn Write+Send>>,
+    loggers: BTreeMap<usize, TaskLogger>,
+    parallelism: usize,
+    started: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct ResultRecord<'a> {
+    id: usize,
+    #[serde(flatten)]
+    task: &'a MultiplexerResultDataTask,
 }
 
 impl<'a> TaskEventReporter<'a> {
-    pub async fn run(self) {
+    pub async fn run(mut self) {
         let mut remaining = self.tasks.len();
         crossterm::execute!(std::io::stderr(), EnterAlternateScreen).unwrap();
-        for event in self.rx {
+        loop {
+            // blocking on the channel would starve the runtime of workers. Without events,
+            // the screen is redrawn every second to keep the ETA current.
+            let event = match tokio::time::timeout(std::time::Duration::from_secs(1), self.rx.recv_async()).await {
+                | Ok(Ok(v)) => v,
+                | Ok(Err(_)) => break,
+                | Err(_) => {
+                    if remaining > 0 {
+                        Self::draw(&self.tasks, false, &self.footer(remaining));
+                    }
+                    continue;
+                },
+            };
+            if let Some(writer) = &mut self.events {
+                let id = match &event {
+                    | TaskEvent::Update { id, .. } | TaskEvent::Stderr { id, .. } | TaskEvent::Stdout { id, .. } => *id,
+                };
+                let task = self.tasks.get(&id).unwrap().read();
+                let record = EventRecord {
+                    timestamp: Utc::now(),
+                    id,
+                    name: &task.name,
+                    data: EventRecordData::from_event(&event),
+                };
+                // ignore error, the event log must never take down the run
+                let _ = Self::write_record(writer, &record);
+            }
+
             match event {
                 | TaskEvent::Update { id, status } => {
+                    let task = &mut self.tasks.get(&id).unwrap().write();
                     match &status {
-                        | TaskStatus::Completed(_) => remaining -= 1,
+                        | TaskStatus::Running => task.started = Some(Utc::now()),
+                        | TaskStatus::Completed(_) => {
+                            task.ended = Some(Utc::now());
+                            remaining -= 1;
+                        },
                         | _ => {},
                     }
-                    self.tasks.get(&id).unwrap().write().status = status;
+                    task.status = status;
+
+                    if let (Some(writer), TaskStatus::Completed(_)) = (&mut self.results, &task.status) {
+                        let record = ResultRecord {
+                            id,
+                            task: &task.to_result(),
+                        };
+                        // ignore error, same as for the event log
+                        let _ = Self::write_record(writer, &record);
+                    }
                 },
                 | TaskEvent::Stderr { id, line } => {
-                    let stderr = &mut self.tasks.get(&id).unwrap().write().stderr;
-                    stderr.push_back(line);
-                    if stderr.len() > self.stderr {
-                        stderr.pop_front();
+                    if let Some(logger) = self.loggers.get_mut(&id) {
+                        // ignore error, same as for the event log
+                        let _ = logger.stderr(&line);
+                    }
+                    let task = &mut self.tasks.get(&id).unwrap().write();
+                    task.stderr_full.push_str(&line);
+                    task.stderr_full.push('\n');
+                    task.stderr.push_back(line);
+                    if task.stderr.len() > self.stderr {
+                        task.stderr.pop_front();
                     }
                 },
                 | TaskEvent::Stdout { id, content } => {
+                    if let Some(logger) = self.loggers.get_mut(&id) {
+                        // ignore error, same as for the event log
+                        let _ = logger.stdout(&content);
+                    }
                     let task = &mut self.tasks.get(&id).unwrap().write();
-                    task.stdout = content;
+                    task.stdout.push_str(&content);
                 },
             }
 
@@ -244,11 +905,50 @@ impl<'a> TaskEventReporter<'a> {
             if remaining == 0 {
                 crossterm::execute!(std::io::stderr(), LeaveAlternateScreen).unwrap();
             }
-            Self::draw(&self.tasks, remaining == 0);
+            Self::draw(&self.tasks, remaining == 0, &self.footer(remaining));
+        }
+    }
+
+    fn footer(&self, remaining: usize) -> String {
+        let total = self.tasks.len();
+        let progress = format!("{} of {} task(s) done", total - remaining, total);
+        match (remaining, self.eta()) {
+            | (0, _) => format!("{} in {}", progress, format_duration(Utc::now() - self.started)),
+            | (_, Some(eta)) => format!("{}, ETA {}", progress, format_duration(eta)),
+            | (_, None) => progress,
         }
     }
 
-    fn draw(tasks: &BTreeMap<usize, RwLock<Task>>, completed: bool) {
+    // the remaining work spread over the slots longest first, like it is
+    // scheduled. Unknown without an estimate for every remaining task.
+    fn eta(&self) -> Option<Duration> {
+        let now = Utc::now();
+        let mut work = Vec::new();
+        for task in self.tasks.values() {
+            let task = task.read();
+            match task.status {
+                | TaskStatus::Completed(_) => {},
+                | TaskStatus::Running => work.push((task.estimate? - (now - task.started?)).max(Duration::zero())),
+                | TaskStatus::Pending => work.push(task.estimate?),
+            }
+        }
+        work.sort_by(|a, b| b.cmp(a));
+        let mut slots = vec![Duration::zero(); self.parallelism.max(1)];
+        for duration in work {
+            let slot = slots.iter_mut().min().unwrap();
+            *slot += duration;
+        }
+        slots.into_iter().max()
+    }
+
+    fn write_record(writer: &mut Box<dyn Write+Send>, record: &impl serde::Serialize) -> Result<()> {
+        serde_json::to_writer(&mut *writer, record)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn draw(tasks: &BTreeMap<usize, RwLock<Task>>, completed: bool, footer: &str) {
         let mut writer = BufWriter::new(stderr());
         if !completed {
             crossterm::queue!(writer, Clear(ClearType::All)).unwrap();
@@ -257,7 +957,11 @@ impl<'a> TaskEventReporter<'a> {
 
         for item in tasks.iter() {
             let task = item.1.read();
-            crossterm::queue!(writer, Print(format!("⇒ ({})\n", item.0))).unwrap();
+            if task.name == item.0.to_string() {
+                crossterm::queue!(writer, Print(format!("⇒ ({})\n", item.0))).unwrap();
+            } else {
+                crossterm::queue!(writer, Print(format!("⇒ ({}) {}\n", item.0, task.name))).unwrap();
+            }
             let lines = task.command.lines();
             crossterm::queue!(writer, Print(" ↳ Script:\n")).unwrap();
             for l in lines {
@@ -276,6 +980,9 @@ impl<'a> TaskEventReporter<'a> {
                             )
                             .red()
                         },
+                        | TaskStatusCompleted::Cancelled => "CANCELLED (dependency failed)".to_owned().dark_grey(),
+                        | TaskStatusCompleted::Skipped(reason) => format!("SKIPPED ({})", reason).dark_grey(),
+                        | TaskStatusCompleted::Cached => "SUCCESS (cached)".to_owned().green(),
                     }
                 },
             };
@@ -292,10 +999,19 @@ impl<'a> TaskEventReporter<'a> {
         }
 
         crossterm::queue!(writer, Print("\n")).unwrap();
-        crossterm::queue!(writer, Print("Thinking...")).unwrap();
+        crossterm::queue!(writer, Print(footer)).unwrap();
         if completed {
-            crossterm::queue!(writer, Print(" DONE\n")).unwrap();
+            crossterm::queue!(writer, Print("\n")).unwrap();
         }
         writer.flush().unwrap();
     }
 }
+
+fn format_duration(duration: Duration) -> String {
+    let ms = duration.num_milliseconds().max(0);
+    match ms {
+        | ..60_000 => format!("{:.1}s", ms as f64 / 1000.0),
+        | ..3_600_000 => format!("{}m {}s", ms / 60_000, ms / 1000 % 60),
+        | _ => format!("{}h {}m", ms / 3_600_000, ms / 60_000 % 60),
+    }
+}
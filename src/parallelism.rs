@@ -0,0 +1,61 @@
+use anyhow::{anyhow, Result};
+
+// the number of processes that run at once, in config files either a number or
+// a string like on the command line
+#[derive(serde::Deserialize, schemars::JsonSchema, Debug, Clone)]
+#[serde(untagged)]
+pub enum Parallelism {
+    Count(usize),
+    Spec(String),
+}
+
+impl Parallelism {
+    pub fn resolve(&self) -> Result<usize> {
+        match self {
+            | Self::Count(0) => Err(anyhow!("parallelism must be greater than 0")),
+            | Self::Count(v) => Ok(*v),
+            | Self::Spec(v) => parse(v),
+        }
+    }
+}
+
+// a number, "auto" (one process per CPU), a share of the CPUs like "50%" or an
+// offset to the CPUs like "cores-1". Shares and offsets leave at least one, 0
+// and "0%" are rejected.
+pub fn parse(value: &str) -> Result<usize> {
+    let invalid = || {
+        anyhow!(
+            "invalid parallelism \"{}\" (expected a number, \"auto\", a percentage like \"50%\" or an offset like \
+             \"cores-1\")",
+            value
+        )
+    };
+    let cores = cores();
+    let parallelism = if value == "auto" || value == "cores" {
+        cores
+    } else if let Some(percent) = value.strip_suffix('%') {
+        // a share of nothing is rejected like 0, small shares still get one
+        match percent.parse::<usize>().map_err(|_| invalid())? {
+            | 0 => 0,
+            | percent => (cores * percent / 100).max(1),
+        }
+    } else if let Some(offset) = value.strip_prefix("cores-") {
+        cores
+            .saturating_sub(offset.parse::<usize>().map_err(|_| invalid())?)
+            .max(1)
+    } else if let Some(offset) = value.strip_prefix("cores+") {
+        cores + offset.parse::<usize>().map_err(|_| invalid())?
+    } else {
+        value.parse::<usize>().map_err(|_| invalid())?
+    };
+    if parallelism == 0 {
+        return Err(anyhow!("parallelism must be greater than 0"));
+    }
+    Ok(parallelism)
+}
+
+// the CPUs bobr may use, which respects affinity masks and cgroup CPU quotas on
+// Linux
+fn cores() -> usize {
+    std::thread::available_parallelism().map(|v| v.get()).unwrap_or(1)
+}
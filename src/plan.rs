@@ -0,0 +1,177 @@
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+
+use crate::{multiplexer::TaskDefinition, table};
+
+#[derive(Debug, Clone, Copy)]
+pub enum PlanFormat {
+    Table,
+    Json,
+}
+
+impl PlanFormat {
+    pub fn args() -> Vec<&'static str> {
+        vec!["table", "json"]
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            | "table" => Ok(Self::Table),
+            | "json" => Ok(Self::Json),
+            | _ => Err(anyhow!("unknown plan format \"{}\"", value)),
+        }
+    }
+}
+
+// the fully resolved tasks of a run, in the order they would be scheduled
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Plan {
+    pub parallelism: usize,
+    pub tasks: Vec<PlanTask>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PlanTask {
+    pub id: usize,
+    pub name: String,
+    // the config file the task was loaded from, none for the command line
+    pub source: Option<PathBuf>,
+    pub argv: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    // only the variables that differ from the environment of bobr
+    pub env: BTreeMap<String, String>,
+    pub tags: Vec<String>,
+    pub depends_on: Vec<String>,
+    // tasks of a stage can only start once the tasks of the previous stages they
+    // depend on are done
+    pub stage: usize,
+    // the reason the task would not be run
+    pub skip: Option<String>,
+}
+
+impl Plan {
+    pub fn new(tasks: &[TaskDefinition], parallelism: usize) -> Self {
+        let names = tasks
+            .iter()
+            .enumerate()
+            .map(|(i, v)| v.name.clone().unwrap_or_else(|| i.to_string()))
+            .collect::<Vec<_>>();
+        let ids = names
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.as_str(), i))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut stages = vec![None; tasks.len()];
+        for i in 0..tasks.len() {
+            Self::stage(i, tasks, &ids, &mut stages);
+        }
+
+        let tasks = tasks
+            .iter()
+            .enumerate()
+            .map(|(i, task)| {
+                PlanTask {
+                    id: i,
+                    name: names[i].clone(),
+                    source: task.source.clone(),
+                    argv: task.program.iter().chain([&task.command]).cloned().collect(),
+                    cwd: task.cwd.clone(),
+                    env: task
+                        .env
+                        .iter()
+                        .filter(|(k, v)| std::env::var(k).ok().as_ref() != Some(*v))
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect(),
+                    tags: task.tags.clone(),
+                    depends_on: task.depends_on.clone(),
+                    stage: stages[i].unwrap_or(0),
+                    skip: task.skip.clone(),
+                }
+            })
+            .sorted_by_key(|v| (v.stage, v.id))
+            .collect();
+        Self { parallelism, tasks }
+    }
+
+    // one more than the latest stage of the dependencies, dependencies are known to
+    // be acyclic
+    fn stage(
+        id: usize,
+        tasks: &[TaskDefinition],
+        ids: &BTreeMap<&str, usize>,
+        stages: &mut Vec<Option<usize>>,
+    ) -> usize {
+        if let Some(stage) = stages[id] {
+            return stage;
+        }
+        let stage = tasks[id]
+            .depends_on
+            .iter()
+            .filter_map(|v| ids.get(v.as_str()))
+            .map(|v| Self::stage(*v, tasks, ids, stages) + 1)
+            .max()
+            .unwrap_or(0);
+        stages[id] = Some(stage);
+        stage
+    }
+
+    pub fn write(&self, writer: &mut dyn Write, format: PlanFormat) -> Result<()> {
+        match format {
+            | PlanFormat::Json => {
+                serde_json::to_writer(&mut *writer, self)?;
+                writeln!(writer)?;
+            },
+            | PlanFormat::Table => self.write_table(writer)?,
+        }
+        Ok(())
+    }
+
+    fn write_table(&self, writer: &mut dyn Write) -> Result<()> {
+        let header = ["STAGE", "ID", "NAME", "DEPENDS ON", "CWD", "SOURCE"];
+        let rows = [header.iter().map(|v| v.to_string()).collect()]
+            .into_iter()
+            .chain(self.tasks.iter().map(|v| {
+                vec![
+                    v.stage.to_string(),
+                    v.id.to_string(),
+                    v.name.clone(),
+                    match v.depends_on.is_empty() {
+                        | true => "-".to_owned(),
+                        | false => v.depends_on.join(", "),
+                    },
+                    v.cwd
+                        .as_ref()
+                        .map(|v| v.display().to_string())
+                        .unwrap_or_else(|| ".".to_owned()),
+                    v.source
+                        .as_ref()
+                        .map(|v| v.display().to_string())
+                        .unwrap_or_else(|| "command line".to_owned()),
+                ]
+            }))
+            .collect::<Vec<_>>();
+        let lines = table::align(&rows);
+
+        writeln!(writer, "{} task(s), parallelism {}", self.tasks.len(), self.parallelism)?;
+        writeln!(writer, "{}", lines[0])?;
+        for (task, line) in self.tasks.iter().zip(&lines[1..]) {
+            writeln!(writer, "{}", line)?;
+            writeln!(writer, "    argv: {:?}", task.argv)?;
+            for (key, value) in &task.env {
+                writeln!(writer, "    env:  {}={}", key, value)?;
+            }
+            if !task.tags.is_empty() {
+                writeln!(writer, "    tags: {}", task.tags.join(", "))?;
+            }
+            if let Some(reason) = &task.skip {
+                writeln!(writer, "    skip: {}", reason)?;
+            }
+        }
+        Ok(())
+    }
+}
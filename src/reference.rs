@@ -4,7 +4,7 @@ use anyhow::Result;
 use clap_complete::Shell;
 use clap_mangen::Man;
 
-use crate::args::ClapArgumentLoader;
+use crate::{args::ClapArgumentLoader, config::Config};
 
 fn collect_commands() -> Vec<(String, clap::Command)> {
     let mut cmds: Vec<(String, clap::Command)> = Vec::new();
@@ -44,3 +44,14 @@ pub fn build_manpages(outdir: &Path) -> Result<()> {
 
     Ok(())
 }
+
+// the JSON Schema of config files, for completion and validation in editors
+pub fn build_schema(out: Option<&Path>) -> Result<()> {
+    let schema = Config::schema();
+    let content = format!("{}\n", serde_json::to_string_pretty(&schema)?);
+    match out {
+        | Some(path) => std::fs::write(path, content)?,
+        | None => std::io::stdout().write_all(content.as_bytes())?,
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::multiplexer::{MultiplexerResult, MultiplexerResultDataTask, MultiplexerResultTaskStatus};
+
+#[derive(Debug)]
+pub enum ReportFormat {
+    Junit,
+    Tap,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub format: ReportFormat,
+    pub path: String,
+}
+
+impl Report {
+    pub fn args() -> Vec<&'static str> {
+        vec!["junit", "tap"]
+    }
+
+    // parses "<format>[:<path>]", a missing path means stdout
+    pub fn parse(value: &str) -> Result<Self> {
+        let (format, path) = value.split_once(':').unwrap_or((value, "-"));
+        let format = match format {
+            | "junit" => ReportFormat::Junit,
+            | "tap" => ReportFormat::Tap,
+            | _ => {
+                return Err(anyhow::anyhow!(
+                    "unknown report format \"{}\" (expected one of: {})",
+                    format,
+                    Self::args().join(", ")
+                ))
+            },
+        };
+        Ok(Self {
+            format,
+            path: path.to_owned(),
+        })
+    }
+
+    pub fn write(&self, result: &MultiplexerResult) -> Result<()> {
+        let mut writer: Box<dyn Write> = match self.path.as_str() {
+            | "-" => Box::new(std::io::stdout()),
+            | path => Box::new(std::fs::File::create(path)?),
+        };
+        match self.format {
+            | ReportFormat::Junit => write_junit(&mut writer, result)?,
+            | ReportFormat::Tap => write_tap(&mut writer, result)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn seconds(task: &MultiplexerResultDataTask) -> f64 {
+    task.duration()
+        .map(|v| v.num_milliseconds() as f64 / 1000.0)
+        .unwrap_or(0.0)
+}
+
+fn failure_message(task: &MultiplexerResultDataTask) -> String {
+    match task.exit_code {
+        | Some(code) => format!("exited with code {}", code),
+        | None => "terminated without exit code".to_owned(),
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            | '&' => escaped.push_str("&amp;"),
+            | '<' => escaped.push_str("&lt;"),
+            | '>' => escaped.push_str("&gt;"),
+            | '"' => escaped.push_str("&quot;"),
+            | '\'' => escaped.push_str("&apos;"),
+            // control characters are not allowed in XML 1.0 documents
+            | '\t' | '\n' | '\r' => escaped.push(c),
+            | c if c.is_control() => {},
+            | c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_junit(writer: &mut dyn Write, result: &MultiplexerResult) -> Result<()> {
+    let tasks = result.tasks.values().collect::<Vec<_>>();
+    let failures = tasks
+        .iter()
+        .filter(|v| v.status == MultiplexerResultTaskStatus::Failed)
+        .count();
+    let skipped = tasks
+        .iter()
+        .filter(|v| {
+            matches!(
+                v.status,
+                MultiplexerResultTaskStatus::Pending
+                    | MultiplexerResultTaskStatus::Running
+                    | MultiplexerResultTaskStatus::Cancelled
+                    | MultiplexerResultTaskStatus::Skipped
+            )
+        })
+        .count();
+    let time = (result.metadata.ended - result.metadata.started).num_milliseconds() as f64 / 1000.0;
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<testsuites name="bobr" tests="{}" failures="{}" errors="0" skipped="{}" time="{:.3}">"#,
+        tasks.len(),
+        failures,
+        skipped,
+        time
+    )?;
+    writeln!(
+        writer,
+        r#"  <testsuite name="bobr" tests="{}" failures="{}" errors="0" skipped="{}" time="{:.3}" timestamp="{}">"#,
+        tasks.len(),
+        failures,
+        skipped,
+        time,
+        result.metadata.started.to_rfc3339()
+    )?;
+    for task in tasks {
+        writeln!(
+            writer,
+            r#"    <testcase name="{}" classname="bobr" time="{:.3}">"#,
+            escape_xml(&task.name),
+            seconds(task)
+        )?;
+        match task.status {
+            | MultiplexerResultTaskStatus::Success => {},
+            | MultiplexerResultTaskStatus::Failed => {
+                writeln!(
+                    writer,
+                    r#"      <failure message="{}" type="exit_code">{}</failure>"#,
+                    escape_xml(&failure_message(task)),
+                    escape_xml(&task.command)
+                )?;
+            },
+            | MultiplexerResultTaskStatus::Pending | MultiplexerResultTaskStatus::Running => {
+                writeln!(writer, r#"      <skipped message="not completed"/>"#)?;
+            },
+            | MultiplexerResultTaskStatus::Cancelled => {
+                writeln!(writer, r#"      <skipped message="dependency failed"/>"#)?;
+            },
+            | MultiplexerResultTaskStatus::Skipped => {
+                writeln!(writer, r#"      <skipped message="nothing to do"/>"#)?;
+            },
+        }
+        writeln!(writer, "      <system-out>{}</system-out>", escape_xml(&task.stdout))?;
+        writeln!(writer, "      <system-err>{}</system-err>", escape_xml(&task.stderr))?;
+        writeln!(writer, "    </testcase>")?;
+    }
+    writeln!(writer, "  </testsuite>")?;
+    writeln!(writer, "</testsuites>")?;
+    Ok(())
+}
+
+fn write_tap(writer: &mut dyn Write, result: &MultiplexerResult) -> Result<()> {
+    writeln!(writer, "TAP version 13")?;
+    writeln!(writer, "1..{}", result.tasks.len())?;
+    for (i, task) in result.tasks.values().enumerate() {
+        // "#" starts a directive in TAP, so it must not appear in the description
+        let description = task.name.replace('#', "\\#");
+        match task.status {
+            | MultiplexerResultTaskStatus::Success => writeln!(writer, "ok {} - {}", i + 1, description)?,
+            | MultiplexerResultTaskStatus::Failed => {
+                writeln!(writer, "not ok {} - {}", i + 1, description)?;
+                writeln!(writer, "  ---")?;
+                writeln!(writer, "  message: \"{}\"", failure_message(task))?;
+                writeln!(
+                    writer,
+                    "  duration_ms: {}",
+                    task.duration().unwrap_or_default().num_milliseconds()
+                )?;
+                if !task.stderr.is_empty() {
+                    writeln!(writer, "  stderr: |")?;
+                    for line in task.stderr.lines() {
+                        writeln!(writer, "    {}", line)?;
+                    }
+                }
+                writeln!(writer, "  ...")?;
+            },
+            | MultiplexerResultTaskStatus::Pending | MultiplexerResultTaskStatus::Running => {
+                writeln!(writer, "ok {} - {} # SKIP not completed", i + 1, description)?;
+            },
+            | MultiplexerResultTaskStatus::Cancelled => {
+                writeln!(writer, "ok {} - {} # SKIP dependency failed", i + 1, description)?;
+            },
+            | MultiplexerResultTaskStatus::Skipped => {
+                writeln!(writer, "ok {} - {} # SKIP nothing to do", i + 1, description)?;
+            },
+        }
+    }
+    Ok(())
+}
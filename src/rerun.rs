@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    history,
+    multiplexer::{MultiplexerResult, MultiplexerResultDataTask, MultiplexerResultTaskStatus, TaskDefinition},
+};
+
+// the tasks of a stored run (the latest by default) or of an exported JSON
+// result file, with their original program, cwd and env. Results do not hold
+// the env, their tasks run in the environment of bobr. With "failed" only the
+// tasks that did not succeed are run again.
+pub fn tasks(source: Option<&str>, failed: bool) -> Result<Vec<TaskDefinition>> {
+    let (tasks, base) = match source {
+        | Some(path) if Path::new(path).is_file() => {
+            let result = serde_json::from_str::<MultiplexerResult>(&std::fs::read_to_string(path)?)
+                .map_err(|e| anyhow!("failed to load the result \"{}\": {}", path, e))?;
+            (result.tasks.into_values().collect::<Vec<_>>(), None)
+        },
+        | _ => {
+            let run = history::load(source)?;
+            let tasks = run
+                .tasks
+                .into_values()
+                .map(|v| MultiplexerResultDataTask { env: v.env, ..v.task })
+                .collect();
+            (tasks, Some(run.cwd))
+        },
+    };
+    if tasks.iter().any(|v| v.program.is_empty()) {
+        return Err(anyhow!(
+            "the result does not contain the resolved tasks, it was written by an older version"
+        ));
+    }
+
+    // tasks of an interrupted run may not have completed
+    let rerun = |task: &MultiplexerResultDataTask| {
+        !failed
+            || matches!(
+                task.status,
+                MultiplexerResultTaskStatus::Failed
+                    | MultiplexerResultTaskStatus::Cancelled
+                    | MultiplexerResultTaskStatus::Pending
+                    | MultiplexerResultTaskStatus::Running
+            )
+    };
+    let tasks = tasks.into_iter().filter(rerun).collect::<Vec<_>>();
+    if tasks.is_empty() {
+        return Err(anyhow!("no failed tasks to run again"));
+    }
+    let names = tasks.iter().map(|v| v.name.clone()).collect::<Vec<_>>();
+    Ok(tasks
+        .into_iter()
+        .map(|task| {
+            TaskDefinition {
+                // dependencies that succeeded before are satisfied
+                depends_on: task.depends_on.into_iter().filter(|v| names.contains(v)).collect(),
+                order_only: false,
+                name: Some(task.name),
+                description: None,
+                program: task.program,
+                command: task.command,
+                // runs stored by older versions have a cwd relative to the directory of the
+                // run, like the tasks without a cwd
+                cwd: match (&base, task.cwd) {
+                    | (Some(base), Some(cwd)) => Some(base.join(cwd)),
+                    | (Some(base), None) => Some(base.clone()),
+                    | (None, cwd) => cwd,
+                },
+                env: task.env,
+                log: None,
+                tags: Vec::new(),
+                source: None,
+                paths: Vec::new(),
+                skip: None,
+                cache: None,
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::multiplexer::TaskDefinition;
+
+// filters the tasks of a run, every filter is a task name, a tag or a glob
+// pattern for task names
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub only: Vec<String>,
+    pub skip: Vec<String>,
+}
+
+struct Filter {
+    value: String,
+    pattern: glob::Pattern,
+}
+
+impl Filter {
+    fn new(value: &str) -> Result<Self> {
+        Ok(Self {
+            value: value.to_owned(),
+            pattern: glob::Pattern::new(value).map_err(|e| anyhow!("invalid filter \"{}\": {}", value, e))?,
+        })
+    }
+
+    // a name also matches all tasks expanded from it by a matrix
+    fn matches(&self, name: &str, task: &TaskDefinition) -> bool {
+        name == self.value
+            || name.strip_prefix(&self.value).is_some_and(|v| v.starts_with('['))
+            || task.tags.contains(&self.value)
+            || self.pattern.matches(name)
+    }
+}
+
+impl Selection {
+    // keeps the tasks matching "only" (all if empty) that do not match "skip".
+    // Dependencies of kept tasks are kept as well, unless they are skipped.
+    pub fn apply(&self, tasks: Vec<TaskDefinition>) -> Result<Vec<TaskDefinition>> {
+        if self.only.is_empty() && self.skip.is_empty() {
+            return Ok(tasks);
+        }
+        // unnamed tasks can still be selected by their index
+        let names = tasks
+            .iter()
+            .enumerate()
+            .map(|(i, v)| v.name.clone().unwrap_or_else(|| i.to_string()))
+            .collect::<Vec<_>>();
+        let matching = |filters: &[String]| -> Result<Vec<bool>> {
+            let mut matching = vec![false; tasks.len()];
+            for filter in filters {
+                let filter = Filter::new(filter)?;
+                let mut found = false;
+                for (i, task) in tasks.iter().enumerate() {
+                    if filter.matches(&names[i], task) {
+                        matching[i] = true;
+                        found = true;
+                    }
+                }
+                if !found {
+                    return Err(anyhow!("no task matches the filter \"{}\"", filter.value));
+                }
+            }
+            Ok(matching)
+        };
+        let skipped = matching(&self.skip)?;
+        let mut selected = match self.only.is_empty() {
+            | true => vec![true; tasks.len()],
+            | false => matching(&self.only)?,
+        };
+        for (i, skipped) in skipped.iter().enumerate() {
+            selected[i] &= !skipped;
+        }
+
+        let ids = names
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.as_str(), i))
+            .collect::<BTreeMap<_, _>>();
+        let mut queue = (0..tasks.len()).filter(|i| selected[*i]).collect::<Vec<_>>();
+        while let Some(i) = queue.pop() {
+            for dependency in &tasks[i].depends_on {
+                if let Some(&id) = ids.get(dependency.as_str()) {
+                    if !selected[id] && !skipped[id] {
+                        selected[id] = true;
+                        queue.push(id);
+                    }
+                }
+            }
+        }
+
+        Ok(tasks
+            .into_iter()
+            .zip(selected)
+            .filter_map(|(mut task, selected)| {
+                // dependencies on skipped tasks are considered satisfied
+                task.depends_on
+                    .retain(|v| ids.get(v.as_str()).is_some_and(|id| !skipped[*id]));
+                selected.then_some(task)
+            })
+            .collect())
+    }
+}
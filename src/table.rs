@@ -0,0 +1,26 @@
+use itertools::Itertools;
+
+// pads the columns of the given rows (the first one being the header) to a
+// common width
+pub fn align(rows: &[Vec<String>]) -> Vec<String> {
+    let columns = rows.iter().map(|v| v.len()).max().unwrap_or(0);
+    let widths = (0..columns)
+        .map(|i| {
+            rows.iter()
+                .filter_map(|v| v.get(i))
+                .map(|v| v.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(v, width)| format!("{:width$}", v, width = width))
+                .join("  ")
+                .trim_end()
+                .to_owned()
+        })
+        .collect()
+}
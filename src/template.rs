@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+
+// values available to templates, referenced as `{{ <namespace>.<key> }}`
+#[derive(Default)]
+pub struct Context<'a> {
+    pub vars: Option<&'a BTreeMap<String, String>>,
+    pub matrix: Option<&'a BTreeMap<String, String>>,
+    pub env: Option<&'a BTreeMap<String, String>>,
+}
+
+impl<'a> Context<'a> {
+    // none for expressions of other template languages, like `{{.Names}}` of
+    // docker or `{{ .Values.x }}` of helm
+    fn lookup(&self, expression: &str) -> Result<Option<String>> {
+        let Some((namespace, key)) = expression.split_once('.') else {
+            return Ok(None);
+        };
+        let values = match namespace {
+            | "vars" => self.vars,
+            | "matrix" => self.matrix,
+            // the environment of bobr itself
+            | "env" => {
+                return std::env::var(key)
+                    .map(Some)
+                    .map_err(|_| anyhow!("undefined template variable \"{}\"", expression));
+            },
+            | _ => return Ok(None),
+        };
+        values
+            .and_then(|v| v.get(key))
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| anyhow!("undefined template variable \"{}\"", expression))
+    }
+}
+
+// expands `{{ vars.name }}` expressions and `${VAR}` variables of the task env.
+// Other expressions, all other variables and shell syntax (like
+// `${VAR:-default}`) are left as they are. `{{{{` escapes a literal `{{` and
+// `$${` escapes the expansion.
+pub fn render(input: &str, context: &Context) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(pos) = rest.find(['{', '$']) {
+        output.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if let Some(escaped) = rest.strip_prefix("{{{{") {
+            output.push_str("{{");
+            rest = escaped;
+        } else if let Some(expression) = rest.strip_prefix("{{") {
+            let value = match expression.find("}}") {
+                | Some(end) => context.lookup(expression[..end].trim())?.map(|v| (v, end)),
+                | None => None,
+            };
+            match value {
+                | Some((value, end)) => {
+                    output.push_str(&value);
+                    rest = &expression[end + 2..];
+                },
+                | None => {
+                    output.push_str("{{");
+                    rest = expression;
+                },
+            }
+        } else if let Some(escaped) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = escaped;
+        } else if let Some(variable) = rest.strip_prefix("${") {
+            let name = variable.split_once('}').map(|v| v.0).filter(|v| is_env_name(v));
+            match name.and_then(|name| context.env.and_then(|v| v.get(name)).map(|value| (name, value))) {
+                | Some((name, value)) => {
+                    output.push_str(value);
+                    rest = &variable[name.len() + 1..];
+                },
+                | None => {
+                    output.push_str("${");
+                    rest = variable;
+                },
+            }
+        } else {
+            output.push_str(&rest[..1]);
+            rest = &rest[1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}